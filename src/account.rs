@@ -10,6 +10,16 @@ pub struct Account {
     pub port: u16,
     pub secure: bool,
     pub sync: Option<u64>,
+    /// Mailboxes to watch, wildcards `*` and `%` can be used
+    pub mailboxes: Option<Vec<String>>,
+    /// Days before replying again to the same address
+    pub reply_interval: Option<u64>,
+    /// Flags never removed by `clear flags`
+    pub preserved_flags: Option<Vec<String>>,
+    /// Timezone of dates and times given in rules, `local` by default
+    pub timezone: Option<String>,
+    /// Move mails of mailing lists to `Lists/<list name>` when no rule moved them
+    pub file_lists: Option<bool>,
 }
 
 impl Account {
@@ -17,4 +27,12 @@ impl Account {
     pub fn from_toml(toml: &Value) -> Result<Account> {
         toml.clone().try_into().map_err(|e| e.into())
     }
+
+    /// Mailboxes to watch, INBOX if none are given
+    pub fn mailboxes(&self) -> Vec<String> {
+        match self.mailboxes {
+            Some(ref mailboxes) if !mailboxes.is_empty() => mailboxes.clone(),
+            _ => vec!["INBOX".to_string()],
+        }
+    }
 }
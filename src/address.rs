@@ -0,0 +1,423 @@
+use decode::decode_words;
+use mail::MailAddress;
+use std::fmt;
+
+/// Characters ending an atom, they are specials of RFC 5322 except `.` and `\`
+const SPECIALS: &str = "()<>[]:;@,\"";
+
+/// Lexical token of an address list
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// Atom or dot-atom, like `john.doe`
+    Atom(String),
+    /// Content of quoted string, without quotes and escapes
+    Quoted(String),
+    /// Content of comment, without its outer parentheses
+    Comment(String),
+    /// Domain literal with its brackets, like `[127.0.0.1]`
+    Literal(String),
+    /// One of `<>:;@,`, or a stray `)` or `]`
+    Special(char),
+}
+
+impl Token {
+    /// Text of word as in a display name
+    fn text(&self) -> &str {
+        match *self {
+            Token::Atom(ref text) |
+            Token::Quoted(ref text) |
+            Token::Comment(ref text) |
+            Token::Literal(ref text) => text,
+            Token::Special(_) => "",
+        }
+    }
+}
+
+/// Check if character is allowed in an atom (RFC 5322 atext), non-ascii is allowed (RFC 6532)
+fn is_atext(c: char) -> bool {
+    c.is_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c) ||
+        (c as u32 > 0x7f && !c.is_whitespace())
+}
+
+/// Check if text is a dot-atom, like `john.doe`
+fn is_dot_atom(text: &str) -> bool {
+    !text.is_empty() && text.split('.').all(|atom| !atom.is_empty() && atom.chars().all(is_atext))
+}
+
+/// Quote text and escape its quotes and backslashes
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Read quoted string, comment or domain literal until its closing character
+///
+/// Backslash escapes next character and folding line breaks are removed. Comments can be nested,
+/// inner parentheses are kept.
+fn read_delimited<I: Iterator<Item = char>>(chars: &mut I, open: char, close: char) -> String {
+    let mut text = String::new();
+    let mut depth = 1;
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(c) = chars.next() {
+                text.push(c);
+            }
+            continue;
+        }
+        if c == close && open != close {
+            depth -= 1;
+            if depth == 0 {
+                break;
+            }
+        } else if c == close {
+            break;
+        } else if c == open {
+            depth += 1;
+        } else if c == '\r' || c == '\n' {
+            continue;
+        }
+        text.push(c);
+    }
+    text
+}
+
+/// Split address list in tokens, whitespace and line folding are dropped
+fn tokenize(value: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => tokens.push(Token::Quoted(read_delimited(&mut chars, '"', '"'))),
+            '(' => {
+                let comment = read_delimited(&mut chars, '(', ')');
+                tokens.push(Token::Comment(comment.trim().to_string()));
+            }
+            '[' => {
+                let literal = read_delimited(&mut chars, '[', ']');
+                let literal = literal.split_whitespace().collect::<String>();
+                tokens.push(Token::Literal(format!("[{}]", literal)));
+            }
+            c if c.is_whitespace() => {}
+            c if SPECIALS.contains(c) => tokens.push(Token::Special(c)),
+            c => {
+                let mut atom = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || SPECIALS.contains(c) {
+                        break;
+                    }
+                    atom.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Atom(atom));
+            }
+        }
+    }
+    tokens
+}
+
+/// Join words of a display name with single spaces
+fn phrase(words: &[Token]) -> String {
+    words.iter().map(Token::text).collect::<Vec<_>>().join(" ")
+}
+
+/// Build address from tokens of an addr-spec, whitespace and comments are dropped
+///
+/// Quoted local parts are kept quoted, unless quotes are useless like in `"john"@example.com`.
+fn addr_spec(tokens: &[Token]) -> String {
+    let mut address = String::new();
+    for token in tokens {
+        match *token {
+            Token::Atom(ref text) | Token::Literal(ref text) => address.push_str(text),
+            Token::Quoted(ref text) if is_dot_atom(text) => address.push_str(text),
+            Token::Quoted(ref text) => address.push_str(&quote(text)),
+            Token::Special('@') => address.push('@'),
+            _ => {}
+        }
+    }
+    address
+}
+
+/// Recursive descent parser of address list tokens, errors are skipped
+struct Parser {
+    tokens: Vec<Token>,
+    idx: usize,
+}
+
+impl Parser {
+    /// Parse whole list, groups are replaced by their members
+    fn address_list(&mut self) -> Vec<MailAddress> {
+        let mut addresses = Vec::new();
+        while self.idx < self.tokens.len() {
+            self.address(false, &mut addresses);
+        }
+        addresses
+    }
+
+    /// Parse mailbox or group and its following comma
+    ///
+    /// In a group, the `;` ending it is left to be read by group.
+    fn address(&mut self, in_group: bool, addresses: &mut Vec<MailAddress>) {
+        let mut words = Vec::new();
+        let mut comment = None;
+        while let Some(token) = self.tokens.get(self.idx).cloned() {
+            self.idx += 1;
+            match token {
+                Token::Atom(_) | Token::Quoted(_) | Token::Literal(_) => words.push(token),
+                Token::Comment(text) if comment.is_none() && !text.is_empty() => {
+                    comment = Some(text);
+                }
+                Token::Special('<') => {
+                    let address = self.angle_addr();
+                    self.mailbox(in_group, phrase(&words), address, comment, addresses);
+                    return;
+                }
+                Token::Special('@') => {
+                    let mut spec = words.clone();
+                    spec.push(Token::Special('@'));
+                    while let Some(token) = self.tokens.get(self.idx).cloned() {
+                        match token {
+                            Token::Atom(_) | Token::Literal(_) => spec.push(token),
+                            _ => break,
+                        }
+                        self.idx += 1;
+                    }
+                    self.mailbox(in_group, String::new(), addr_spec(&spec), comment, addresses);
+                    return;
+                }
+                Token::Special(':') if !in_group => {
+                    self.group(addresses);
+                    return;
+                }
+                Token::Special(',') => break,
+                Token::Special(';') if in_group => {
+                    self.idx -= 1;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        // Words alone are kept as a local address, like `root`
+        let address = phrase(&words);
+        if !address.trim().is_empty() {
+            addresses.push(MailAddress::new(comment.unwrap_or_default(), address));
+        }
+    }
+
+    /// Parse address after `<` until `>`, obsolete routes like `<@relay.com:john@example.com>`
+    /// are dropped
+    fn angle_addr(&mut self) -> String {
+        let mut spec = Vec::new();
+        while let Some(token) = self.tokens.get(self.idx).cloned() {
+            self.idx += 1;
+            match token {
+                Token::Special('>') => break,
+                Token::Special(':') if spec.first() == Some(&Token::Special('@')) => spec.clear(),
+                token => spec.push(token),
+            }
+        }
+        addr_spec(&spec)
+    }
+
+    /// Skip tokens after address until next comma and add mailbox
+    ///
+    /// Without display name, first comment is used as name, like in `john@example.com (John)`.
+    fn mailbox(
+        &mut self,
+        in_group: bool,
+        name: String,
+        address: String,
+        mut comment: Option<String>,
+        addresses: &mut Vec<MailAddress>,
+    ) {
+        while let Some(token) = self.tokens.get(self.idx).cloned() {
+            match token {
+                Token::Special(';') if in_group => break,
+                Token::Special(',') => {
+                    self.idx += 1;
+                    break;
+                }
+                Token::Comment(text) if comment.is_none() && !text.is_empty() => {
+                    comment = Some(text);
+                }
+                _ => {}
+            }
+            self.idx += 1;
+        }
+        if address.is_empty() {
+            return;
+        }
+        let name = if name.is_empty() {
+            comment.unwrap_or_default()
+        } else {
+            name
+        };
+        addresses.push(MailAddress::new(name, address));
+    }
+
+    /// Parse members of group after its `:`, until `;`
+    fn group(&mut self, addresses: &mut Vec<MailAddress>) {
+        while let Some(token) = self.tokens.get(self.idx).cloned() {
+            if token == Token::Special(';') {
+                self.idx += 1;
+                return;
+            }
+            self.address(true, addresses);
+        }
+    }
+}
+
+/// Parse address list of a header like To (RFC 5322 section 3.4, with obsolete syntax)
+///
+/// Parsing never fails, invalid parts are skipped. Members of groups are listed like other
+/// addresses and empty groups like `undisclosed-recipients:;` give no address. Encoded words of
+/// display names are decoded.
+pub fn parse_address_list(value: &str) -> Vec<MailAddress> {
+    let mut addresses = Parser {
+        tokens: tokenize(value),
+        idx: 0,
+    }.address_list();
+    for address in &mut addresses {
+        address.name = decode_words(&address.name);
+    }
+    addresses
+}
+
+impl fmt::Display for MailAddress {
+    /// Format address for a header, display name is quoted when needed
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.name.is_empty() {
+            return write!(f, "<{}>", self.address);
+        }
+        let plain = !self.name.starts_with(' ') && !self.name.ends_with(' ') &&
+            !self.name.contains("  ") &&
+            self.name.chars().all(|c| c == ' ' || is_atext(c));
+        if plain {
+            write!(f, "{} <{}>", self.name, self.address)
+        } else {
+            write!(f, "{} <{}>", quote(&self.name), self.address)
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    fn address(name: &str, address: &str) -> MailAddress {
+        MailAddress::new(name.to_string(), address.to_string())
+    }
+
+    #[test]
+    fn address_list_examples() {
+        let cases = vec![
+            ("Test <test@test.com>", vec![address("Test", "test@test.com")]),
+            ("\"Doe, John\" <jd@x.com>", vec![address("Doe, John", "jd@x.com")]),
+            ("a@x.com,b@y.com", vec![address("", "a@x.com"), address("", "b@y.com")]),
+            ("undisclosed-recipients:;", vec![]),
+            ("", vec![]),
+            (" , ,", vec![]),
+            (
+                "Team: a@x.com, \"B\" <b@x.com>; c@x.com",
+                vec![address("", "a@x.com"), address("B", "b@x.com"), address("", "c@x.com")],
+            ),
+            ("Empty: ;, d@x.com", vec![address("", "d@x.com")]),
+            ("jd@x.com (John Doe)", vec![address("John Doe", "jd@x.com")]),
+            ("John (the (great) man) Doe <jd@x.com>", vec![address("John Doe", "jd@x.com")]),
+            ("<@relay.com,@other.com:jd@x.com>", vec![address("", "jd@x.com")]),
+            ("\"john doe\"@x.com", vec![address("", "\"john doe\"@x.com")]),
+            ("\"jd\"@x.com", vec![address("", "jd@x.com")]),
+            ("jd @ x . com", vec![address("", "jd@x.com")]),
+            ("John Q. Public <jqp@[127.0.0.1]>", vec![address("John Q. Public", "jqp@[127.0.0.1]")]),
+            ("\"Quote \\\" here\" <q@x.com>", vec![address("Quote \" here", "q@x.com")]),
+            ("John\r\n Doe <jd@x.com>", vec![address("John Doe", "jd@x.com")]),
+            ("root", vec![address("", "root")]),
+            ("Nobody <>", vec![]),
+            ("Élodie <élodie@exemple.fr>", vec![address("Élodie", "élodie@exemple.fr")]),
+            (
+                "=?UTF-8?Q?J=C3=BCrgen_Doe?= <jd@x.com>, =?ISO-8859-1?Q?M=FCller?= <m@x.com>",
+                vec![address("Jürgen Doe", "jd@x.com"), address("Müller", "m@x.com")],
+            ),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_address_list(value), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn address_display() {
+        assert_eq!(address("", "a@x.com").to_string(), "<a@x.com>");
+        assert_eq!(address("John Doe", "jd@x.com").to_string(), "John Doe <jd@x.com>");
+        assert_eq!(address("Doe, John", "jd@x.com").to_string(), "\"Doe, John\" <jd@x.com>");
+        assert_eq!(address("a \"b\"", "q@x.com").to_string(), "\"a \\\"b\\\"\" <q@x.com>");
+    }
+
+    /// Random text made of characters likely to confuse parser
+    fn random_text<R: Rng>(rng: &mut R, max_len: usize) -> String {
+        let alphabet: Vec<char> = "ab.z09 \"\\()<>[]:;@,\r\n\t!#=?é".chars().collect();
+        let len = rng.gen_range(0, max_len + 1);
+        (0..len).map(|_| *rng.choose(&alphabet).unwrap()).collect()
+    }
+
+    /// Random local part, dot-atom or quoted
+    fn random_local<R: Rng>(rng: &mut R) -> String {
+        let atoms: Vec<String> = (0..rng.gen_range(1, 4))
+            .map(|_| {
+                let len = rng.gen_range(1, 6);
+                (0..len)
+                    .map(|_| *rng.choose(&['a', 'b', 'z', '0', '+', '-', '_', 'é']).unwrap())
+                    .collect()
+            })
+            .collect();
+        let local = atoms.join(".");
+        if rng.gen_weighted_bool(3) {
+            let text = random_text(rng, 3).replace(|c| c == '\r' || c == '\n', "");
+            quote(&format!("{} {}", local, text))
+        } else {
+            local
+        }
+    }
+
+    #[test]
+    fn address_list_never_fails() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for _ in 0..5000 {
+            let value = random_text(&mut rng, 40);
+            for address in parse_address_list(&value) {
+                assert!(!address.address.is_empty(), "{:?}", value);
+            }
+        }
+    }
+
+    #[test]
+    fn address_list_round_trip() {
+        let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+        for _ in 0..2000 {
+            let addresses: Vec<MailAddress> = (0..rng.gen_range(0, 5))
+                .map(|_| {
+                    let name = random_text(&mut rng, 12).replace(|c| c == '\r' || c == '\n', "");
+                    let domain = if rng.gen_weighted_bool(5) {
+                        "[10.0.0.1]"
+                    } else {
+                        *rng.choose(&["x.com", "a.b.c", "exemple.fr"]).unwrap()
+                    };
+                    MailAddress::new(name, format!("{}@{}", random_local(&mut rng), domain))
+                })
+                .collect();
+            let separator = *rng.choose(&[",", ", ", " ,\r\n\t", ",(comment),"]).unwrap();
+            let value = addresses
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+                .join(separator);
+            assert_eq!(parse_address_list(&value), addresses, "{:?}", value);
+        }
+    }
+}
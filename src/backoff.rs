@@ -0,0 +1,76 @@
+use rand::{thread_rng, Rng};
+use std::cmp;
+use std::time::Duration;
+
+/// Exponential backoff with jitter, used between reconnection attempts
+#[derive(Debug)]
+pub struct Backoff {
+    min: u64,
+    max: u64,
+    attempt: u32,
+}
+
+impl Backoff {
+    /// Create a backoff growing from `min` to `max` seconds
+    pub fn new(min: u64, max: u64) -> Backoff {
+        Backoff {
+            min: cmp::max(min, 1),
+            max: cmp::max(max, min),
+            attempt: 0,
+        }
+    }
+
+    /// Forget previous attempts, to call once connection succeed
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    /// Upper bound of the next delay, in seconds
+    fn ceiling(&self) -> u64 {
+        let factor = 1u64.checked_shl(self.attempt).unwrap_or(u64::MAX);
+        cmp::min(self.min.saturating_mul(factor), self.max)
+    }
+
+    /// Get delay to wait before next attempt
+    ///
+    /// Delay is picked randomly between half and all of the ceiling, so accounts
+    /// disconnected at the same time don't reconnect at the same time.
+    pub fn next(&mut self) -> Duration {
+        let ceiling = self.ceiling();
+        if ceiling < self.max {
+            self.attempt += 1;
+        }
+        let millis = ceiling * 1000;
+        Duration::from_millis(thread_rng().gen_range(millis / 2, millis + 1))
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grow() {
+        let mut backoff = Backoff::new(1, 60);
+        for ceiling in &[1, 2, 4, 8, 16, 32, 60, 60, 60] {
+            let delay = backoff.next();
+            assert!(delay <= Duration::from_secs(*ceiling), "{:?} > {}", delay, ceiling);
+            assert!(
+                delay >= Duration::from_millis(ceiling * 500),
+                "{:?} < {}/2",
+                delay,
+                ceiling
+            );
+        }
+    }
+
+    #[test]
+    fn backoff_reset() {
+        let mut backoff = Backoff::new(2, 60);
+        for _ in 0..10 {
+            backoff.next();
+        }
+        backoff.reset();
+        assert!(backoff.next() <= Duration::from_secs(2));
+    }
+}
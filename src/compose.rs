@@ -0,0 +1,294 @@
+use chrono::Local;
+use mail::{Mail, MailAddress};
+use rand::{thread_rng, Rng};
+
+/// Generate a new message id for a mail sent by `from`
+pub fn message_id(from: &str) -> String {
+    let domain = from.rsplit('@').next().unwrap_or("narricky");
+    format!(
+        "<{}.{:016x}@{}>",
+        Local::now().timestamp(),
+        thread_rng().gen::<u64>(),
+        domain
+    )
+}
+
+/// Make sure every line ends with CRLF
+fn crlf(text: &str) -> String {
+    let mut result = text.lines().collect::<Vec<&str>>().join("\r\n");
+    result.push_str("\r\n");
+    result
+}
+
+/// Make text safe for a header field: line breaks are removed and non ASCII text is written as
+/// encoded words (RFC 2047)
+fn header_text(text: &str) -> String {
+    let text = text.replace(['\r', '\n'], " ");
+    if text.is_ascii() {
+        return text;
+    }
+    // Encoded words are limited to 75 characters, 45 bytes give 60 characters of base64
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in text.chars() {
+        if chunk.len() + c.len_utf8() > 45 {
+            words.push(format!("=?utf-8?B?{}?=", ::base64::encode(chunk.as_bytes())));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    words.push(format!("=?utf-8?B?{}?=", ::base64::encode(chunk.as_bytes())));
+    words.join("\r\n ")
+}
+
+/// Forward mail unchanged, adding Resent- headers on top of it (RFC 5322 section 3.6.6)
+pub fn forward_inline(raw: &str, from: &str, to: &str) -> String {
+    format!(
+        "Resent-From: {}\r\nResent-To: {}\r\nResent-Date: {}\r\nResent-Message-ID: {}\r\n{}",
+        header_text(from),
+        header_text(to),
+        Local::now().to_rfc2822(),
+        message_id(from),
+        crlf(raw)
+    )
+}
+
+/// Forward mail as a message/rfc822 attachment of a new mail
+pub fn forward_attached(raw: &str, mail: &Mail, from: &str, to: &str) -> String {
+    let boundary = format!("narricky-{:016x}", thread_rng().gen::<u64>());
+    let sender = mail.from
+        .iter()
+        .map(|a| a.address.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ");
+    format!(
+        "From: {from}\r\n\
+         To: {to}\r\n\
+         Subject: Fwd: {subject}\r\n\
+         Date: {date}\r\n\
+         Message-ID: {id}\r\n\
+         MIME-Version: 1.0\r\n\
+         Content-Type: multipart/mixed; boundary=\"{boundary}\"\r\n\
+         \r\n\
+         --{boundary}\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         \r\n\
+         Forwarded message from {sender}\r\n\
+         --{boundary}\r\n\
+         Content-Type: message/rfc822\r\n\
+         Content-Disposition: attachment\r\n\
+         \r\n\
+         {raw}\
+         --{boundary}--\r\n",
+        from = header_text(from),
+        to = header_text(to),
+        subject = header_text(mail.subject.trim()),
+        date = Local::now().to_rfc2822(),
+        id = message_id(from),
+        boundary = boundary,
+        sender = sender,
+        raw = crlf(raw)
+    )
+}
+
+/// Local parts of addresses used by robots, they never get automatic replies
+const ROBOT_SENDERS: &[&str] = &[
+    "mailer-daemon",
+    "postmaster",
+    "noreply",
+    "no-reply",
+    "do-not-reply",
+    "donotreply",
+];
+
+/// Address an automatic reply to this mail should be sent to
+pub fn reply_address(mail: &Mail) -> Option<String> {
+    if let Some(reply_to) = mail.header("Reply-To") {
+        let address = MailAddress::parse_imap(reply_to).address;
+        if !address.is_empty() {
+            return Some(address);
+        }
+    }
+    match mail.from.first() {
+        Some(address) if !address.address.is_empty() => Some(address.address.clone()),
+        _ => None,
+    }
+}
+
+/// Check if an automatic reply must not be sent to this mail and give the reason
+///
+/// Mails from mailing lists, bulk mails, automatic mails (RFC 3834) and mails sent by ourself
+/// are never answered, to avoid mail loops.
+pub fn reply_refusal(mail: &Mail, own_addresses: &[&str]) -> Option<&'static str> {
+    if mail.is_mailing_list() {
+        return Some("mail comes from a mailing list");
+    }
+    if let Some(ref precedence) = mail.precedence {
+        if precedence == "bulk" || precedence == "junk" {
+            return Some("mail is bulk mail");
+        }
+    }
+    if let Some(auto) = mail.header("Auto-Submitted") {
+        if !auto.eq_ignore_ascii_case("no") {
+            return Some("mail was automatically submitted");
+        }
+    }
+    if mail.header("Return-Path").map(|p| p.trim() == "<>") == Some(true) {
+        return Some("mail has no return path");
+    }
+    let address = match reply_address(mail) {
+        Some(address) => address.to_lowercase(),
+        None => return Some("mail has no sender"),
+    };
+    if own_addresses.iter().any(
+        |own| own.eq_ignore_ascii_case(&address),
+    )
+    {
+        return Some("mail was sent by ourself");
+    }
+    let local = address.split('@').next().unwrap_or_default();
+    if ROBOT_SENDERS.contains(&local) || local.ends_with("-request") {
+        return Some("mail was sent by a robot");
+    }
+    None
+}
+
+/// Write a reply to mail with given text
+pub fn reply(mail: &Mail, from: &str, to: &str, text: &str) -> String {
+    let subject = mail.subject.trim();
+    let subject = if subject.to_lowercase().starts_with("re:") {
+        subject.to_string()
+    } else {
+        format!("Re: {}", subject)
+    };
+    let mut threading = String::new();
+    if let Some(id) = mail.header("Message-ID") {
+        let references = match mail.header("References").or(mail.header("In-Reply-To")) {
+            Some(references) => format!("{} {}", references, id),
+            None => id.to_string(),
+        };
+        threading = format!(
+            "In-Reply-To: {}\r\nReferences: {}\r\n",
+            header_text(id),
+            header_text(&references)
+        );
+    }
+    format!(
+        "From: {}\r\n\
+         To: {}\r\n\
+         Subject: {}\r\n\
+         Date: {}\r\n\
+         Message-ID: {}\r\n\
+         {}\
+         Auto-Submitted: auto-replied\r\n\
+         MIME-Version: 1.0\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         Content-Transfer-Encoding: 8bit\r\n\
+         \r\n\
+         {}",
+        header_text(from),
+        header_text(to),
+        header_text(&subject),
+        Local::now().to_rfc2822(),
+        message_id(from),
+        threading,
+        crlf(text)
+    )
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    const RAW: &'static str = "Subject: Hehe\r\nFrom: Inconnito <inconnito@superrito.com>\r\n\
+                               To: hineen1975@superrito.com\r\n\r\nHello world\n";
+
+    fn mail() -> Mail {
+        Mail::parse_fetched(vec![RAW.to_string()]).unwrap()
+    }
+
+    #[test]
+    fn compose_forward_inline() {
+        let forward = forward_inline(RAW, "me@test.com", "you@test.com");
+        assert!(forward.starts_with("Resent-From: me@test.com\r\nResent-To: you@test.com\r\n"));
+        assert!(forward.contains("Resent-Message-ID: <"));
+        assert!(forward.ends_with("\r\n\r\nHello world\r\n"));
+    }
+
+    #[test]
+    fn compose_forward_attached() {
+        let forward = forward_attached(RAW, &mail(), "me@test.com", "you@test.com");
+        assert!(forward.starts_with("From: me@test.com\r\nTo: you@test.com\r\nSubject: Fwd: Hehe\r\n"));
+        assert!(forward.contains("Forwarded message from inconnito@superrito.com\r\n"));
+        assert!(forward.contains(
+            "Content-Type: message/rfc822\r\nContent-Disposition: attachment\r\n\r\nSubject: Hehe\r\n",
+        ));
+        assert!(forward.trim_right().ends_with("--"));
+    }
+
+    fn mail_with(headers: &str) -> Mail {
+        Mail::parse_fetched(vec![
+            format!("Subject: Hehe\r\nFrom: Inconnito <inconnito@superrito.com>\r\n{}\r\n", headers),
+        ]).unwrap()
+    }
+
+    #[test]
+    fn compose_reply() {
+        let mail = mail_with("Message-ID: <2@test>\r\nReferences: <1@test>\r\n");
+        let message = reply(&mail, "me@test.com", "inconnito@superrito.com", "Thanks");
+        assert!(message.contains("Subject: Re: Hehe\r\n"));
+        assert!(message.contains("In-Reply-To: <2@test>\r\nReferences: <1@test> <2@test>\r\n"));
+        assert!(message.contains("Auto-Submitted: auto-replied\r\n"));
+        assert!(message.ends_with("\r\n\r\nThanks\r\n"));
+        let mut mail = mail_with("");
+        mail.subject = "RE: Hehe".to_string();
+        let message = reply(&mail, "me@test.com", "inconnito@superrito.com", "Thanks");
+        assert!(message.contains("Subject: RE: Hehe\r\n"));
+        assert!(!message.contains("In-Reply-To"));
+    }
+
+    #[test]
+    fn compose_header_text() {
+        assert_eq!(header_text("Hehe"), "Hehe");
+        assert_eq!(header_text("Hehe\r\nBcc: a@b.com"), "Hehe  Bcc: a@b.com");
+        assert_eq!(header_text("Café"), "=?utf-8?B?Q2Fmw6k=?=");
+        let long = header_text(&"é".repeat(30));
+        let words = long.split("\r\n ").collect::<Vec<&str>>();
+        assert_eq!(words.len(), 2);
+        assert!(words.iter().all(|w| w.len() <= 75));
+        let mut mail = mail();
+        mail.subject = "Réunion\r\nBcc: a@b.com".to_string();
+        let message = reply(&mail, "me@test.com", "inconnito@superrito.com", "Thanks");
+        assert!(message.contains("Subject: =?utf-8?B?"));
+        assert!(!message.contains("\r\nBcc"));
+        let forward = forward_attached(RAW, &mail, "me@test.com", "you@test.com");
+        assert!(forward.contains("Subject: Fwd: =?utf-8?B?"));
+        assert!(!forward.contains("\r\nBcc"));
+    }
+
+    #[test]
+    fn compose_reply_address() {
+        assert_eq!(
+            reply_address(&mail_with("")),
+            Some("inconnito@superrito.com".to_string())
+        );
+        assert_eq!(
+            reply_address(&mail_with("Reply-To: Other <other@test.com>\r\n")),
+            Some("other@test.com".to_string())
+        );
+    }
+
+    #[test]
+    fn compose_reply_refusal() {
+        let own = ["me@test.com"];
+        assert_eq!(reply_refusal(&mail_with(""), &own), None);
+        assert!(reply_refusal(&mail_with("List-Id: <rust.lists>\r\n"), &own).is_some());
+        assert!(reply_refusal(&mail_with("Precedence: Bulk\r\n"), &own).is_some());
+        assert!(reply_refusal(&mail_with("Auto-Submitted: auto-replied\r\n"), &own).is_some());
+        assert_eq!(reply_refusal(&mail_with("Auto-Submitted: no\r\n"), &own), None);
+        assert!(reply_refusal(&mail_with("Return-Path: <>\r\n"), &own).is_some());
+        assert!(reply_refusal(&mail_with("Reply-To: ME@test.com\r\n"), &own).is_some());
+        assert!(reply_refusal(&mail_with("Reply-To: MAILER-DAEMON@test.com\r\n"), &own).is_some());
+        assert!(reply_refusal(&mail_with("Reply-To: rust-request@test.com\r\n"), &own).is_some());
+    }
+}
@@ -1,14 +1,20 @@
 use account::Account;
 use error::*;
+use folder::Folders;
 use rule::Rule;
+use smtp::Smtp;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use timezone::Timezone;
 use toml::Value;
 
 /// Configuration for a mail account
 pub struct Config {
     pub account: Account,
+    pub smtp: Option<Smtp>,
+    pub folders: Folders,
+    pub timezone: Timezone,
     pub rules: Vec<Rule>,
 }
 
@@ -22,6 +28,18 @@ impl Config {
                 bail!(ErrorKind::MissingAccount);
             }
         };
+        let smtp = match toml.get("smtp") {
+            Some(val) => Some(Smtp::from_toml(val)?),
+            None => None,
+        };
+        let folders = match toml.get("folders") {
+            Some(val) => Folders::from_toml(val)?,
+            None => Folders::default(),
+        };
+        let timezone = match account.timezone {
+            Some(ref timezone) => Timezone::parse(timezone)?,
+            None => Timezone::default(),
+        };
         if let Some(rule_val) = toml.get("rule") {
             if let Some(rule_val) = rule_val.as_table() {
                 for (name, table) in rule_val.iter() {
@@ -33,11 +51,27 @@ impl Config {
             }
         }
         Ok(Config {
-            account: account,
-            rules: rules,
+            account,
+            smtp,
+            folders,
+            timezone,
+            rules,
         })
     }
 
+    /// Names of headers needed by rules, besides the usual fields
+    pub fn headers(&self) -> Vec<String> {
+        let mut headers: Vec<String> = Vec::new();
+        for rule in &self.rules {
+            for header in rule.expression.headers() {
+                if !headers.iter().any(|h| h.eq_ignore_ascii_case(header)) {
+                    headers.push(header.to_string());
+                }
+            }
+        }
+        headers
+    }
+
     /// Read file and try to return a Config if there is no error
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config> {
         let mut file = File::open(path.as_ref())?;
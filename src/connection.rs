@@ -1,19 +1,62 @@
 use account::Account;
 use error::*;
+use folder::{special_folders, Folders, SpecialUse};
 use imap::client::Client;
 use imap::mailbox::Mailbox;
-use mail::Mail;
+use mail::{normalize_flag, parse_internal_date, Mail};
+use mime::Part;
+use response::{parse_fetch, parse_list, parse_search, parse_thread, ListEntry, Value};
 use openssl::ssl::{SslConnectorBuilder, SslMethod, SslStream};
+use std::io;
 use std::net::TcpStream;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Duration;
+
+/// Headers fetched for each mail, needed by conditions on usual fields and by actions
+const FETCHED_HEADERS: [&str; 15] = [
+    "FROM",
+    "TO",
+    "CC",
+    "SUBJECT",
+    "DATE",
+    "MESSAGE-ID",
+    "IN-REPLY-TO",
+    "REFERENCES",
+    "REPLY-TO",
+    "RETURN-PATH",
+    "AUTO-SUBMITTED",
+    "PRECEDENCE",
+    "LIST-ID",
+    "LIST-POST",
+    "LIST-UNSUBSCRIBE",
+];
 
 enum ConnectionResult {
     Normal(Client<TcpStream>),
     Secure(Client<SslStream<TcpStream>>),
 }
 
-pub struct Connection(ConnectionResult);
+pub struct Connection {
+    client: ConnectionResult,
+    capabilities: Vec<String>,
+    folders: Vec<(SpecialUse, String)>,
+    /// Mails marked as deleted, waiting to be expunged
+    deleted: Vec<u32>,
+}
 
 impl Connection {
+    /// Wrap logged in client and fetch its capabilities
+    fn new(client: ConnectionResult) -> Result<Connection> {
+        let mut connection = Connection {
+            client,
+            capabilities: Vec::new(),
+            folders: Vec::new(),
+            deleted: Vec::new(),
+        };
+        connection.capabilities = connection.capability()?;
+        Ok(connection)
+    }
+
     /// Establish connection with this account
     pub fn connect(account: &Account) -> Result<Connection> {
         if account.secure {
@@ -28,126 +71,242 @@ impl Connection {
             imap_socket
                 .login(&account.username, &account.password)
                 .chain_err(|| "fail when login")?;
-            Ok(Connection(ConnectionResult::Secure(imap_socket)))
+            Connection::new(ConnectionResult::Secure(imap_socket))
         } else {
             let mut imap_socket = Client::connect((account.domain.as_str(), account.port))?;
             imap_socket
                 .login(&account.username, &account.password)
                 .chain_err(|| "fail when login")?;
-            Ok(Connection(ConnectionResult::Normal(imap_socket)))
+            Connection::new(ConnectionResult::Normal(imap_socket))
         }
     }
 
     /// Set debug for connection
     pub fn set_debug(&mut self, debug: bool) {
-        match &mut self.0 {
-            &mut ConnectionResult::Normal(ref mut s) => s.debug = debug,
-            &mut ConnectionResult::Secure(ref mut s) => s.debug = debug,
+        match self.client {
+            ConnectionResult::Normal(ref mut s) => s.debug = debug,
+            ConnectionResult::Secure(ref mut s) => s.debug = debug,
         }
     }
 
     /// Selects a mailbox
     pub fn select(&mut self, mailbox_name: &str) -> Result<Mailbox> {
-        match &mut self.0 {
-            &mut ConnectionResult::Normal(ref mut s) => {
+        match self.client {
+            ConnectionResult::Normal(ref mut s) => {
                 s.select(mailbox_name).chain_err(|| "fail when selecting")
             }
-            &mut ConnectionResult::Secure(ref mut s) => {
+            ConnectionResult::Secure(ref mut s) => {
                 s.select(mailbox_name).chain_err(|| "fail when selecting")
             }
         }
     }
 
-    /// Examine a mailbox
-    /// Fetch data
-    pub fn fetch(&mut self, sequence_set: &str, query: &str) -> Result<Vec<String>> {
-        match &mut self.0 {
-            &mut ConnectionResult::Normal(ref mut s) => {
-                s.fetch(sequence_set, query).chain_err(
+    /// Fetch data using uids
+    pub fn uid_fetch(&mut self, uid_set: &str, query: &str) -> Result<Vec<String>> {
+        match self.client {
+            ConnectionResult::Normal(ref mut s) => {
+                s.uid_fetch(uid_set, query).chain_err(
                     || "fail when fetching",
                 )
             }
-            &mut ConnectionResult::Secure(ref mut s) => {
-                s.fetch(sequence_set, query).chain_err(
+            ConnectionResult::Secure(ref mut s) => {
+                s.uid_fetch(uid_set, query).chain_err(
                     || "fail when fetching",
                 )
             }
         }
     }
 
-    /// Fetch mail
-    pub fn fetch_mail(&mut self, index: usize) -> Result<Mail> {
-        let mut headers = self.fetch(
-            &index.to_string(),
-            "body.peek[header.fields (FROM TO CC SUBJECT)]",
+    /// Fetch several mails, mails which doesn't exist anymore are skipped
+    ///
+    /// `headers` are fetched in addition to the usual ones. Text bodies are fetched once structures
+    /// are known, with one command for mails having their body in the same section.
+    pub fn fetch_mails(&mut self, uids: &[u32], headers: &[String]) -> Result<Vec<Mail>> {
+        if uids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let lines = self.uid_fetch(
+            &uid_set(uids),
+            &format!(
+                "(UID FLAGS INTERNALDATE RFC822.SIZE BODYSTRUCTURE \
+                 BODY.PEEK[HEADER.FIELDS ({})])",
+                header_fields(headers)
+            ),
         )?;
-        let mut text = self.fetch(&index.to_string(), "body.peek[1]")?;
-        headers.remove(0);
-        headers.pop();
-        headers.pop();
-        text.remove(0);
-        text.pop();
-        text.pop();
-        headers.append(&mut text);
-        Mail::parse_fetched(headers)
-    }
-
-    /// Create a mailbox
-    pub fn create(&mut self, mailbox_name: &str) -> Result<()> {
-        // TODO test subfolder
-        let mut list: Vec<String> = mailbox_name.split('/').map(|s| s.to_owned()).collect();
-        let name = list.pop().unwrap_or(mailbox_name.to_owned());
-        let mut folder_name = list.iter().map(|s| format!("{}/", s)).collect::<String>();
-        if folder_name.is_empty() {
-            folder_name = "/".to_owned();
-        }
-        if self.list(&folder_name, &name)?.len() >= 2 {
+        let mut mails = Vec::new();
+        for fetch in parse_fetch(&lines)? {
+            let uid = match fetch.uid() {
+                Some(uid) if uids.contains(&uid) => uid,
+                _ => continue,
+            };
+            let headers = fetch
+                .get_prefix("BODY[HEADER")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            let mut mail = match Mail::parse_fetched(vec![headers]) {
+                Ok(mail) => mail,
+                Err(e) => {
+                    println!("mail {} skipped, headers are invalid: {}", uid, e);
+                    continue;
+                }
+            };
+            mail.uid = uid;
+            mail.received = fetch
+                .get("INTERNALDATE")
+                .and_then(Value::as_str)
+                .and_then(parse_internal_date);
+            mail.size = fetch.get("RFC822.SIZE").and_then(Value::as_number);
+            mail.structure = fetch.get("BODYSTRUCTURE").and_then(Part::from_bodystructure);
+            if let Some(flags) = fetch.get("FLAGS").and_then(Value::as_list) {
+                mail.flags = flags
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(normalize_flag)
+                    .collect();
+            }
+            mails.push(mail);
+        }
+        mails.sort_by_key(|m| m.uid);
+        for (section, section_uids) in body_sections(&mails) {
+            let lines = self.uid_fetch(
+                &uid_set(&section_uids),
+                &format!("(UID BODY.PEEK[{}])", section),
+            )?;
+            for fetch in parse_fetch(&lines)? {
+                let text = match fetch.get(&format!("BODY[{}]", section)).and_then(Value::as_str) {
+                    Some(text) => text,
+                    None => continue,
+                };
+                let mail = match mails.iter_mut().find(|m| Some(m.uid) == fetch.uid()) {
+                    Some(mail) => mail,
+                    None => continue,
+                };
+                let content = match mail.structure.as_ref().and_then(Part::text_body) {
+                    Some((_, part)) => part.decode(text),
+                    None => continue,
+                };
+                mail.content = content;
+            }
+        }
+        Ok(mails)
+    }
+
+    /// Fetch whole mail, headers and body, without altering its flags
+    ///
+    /// Mails with parts which can hold any byte are refused from their structure, imap would
+    /// panic on them.
+    pub fn fetch_raw(&mut self, mail: &Mail) -> Result<String> {
+        let uid = mail.uid;
+        if mail.structure.as_ref().map(Part::is_raw_fetchable) != Some(true) {
+            bail!(ErrorKind::UnfetchableMail(uid));
+        }
+        let lines = self.uid_fetch(&uid.to_string(), "(UID BODY.PEEK[])")?;
+        let raw = parse_fetch(&lines)?
+            .into_iter()
+            .find(|f| f.uid() == Some(uid))
+            .and_then(|f| f.get("BODY[]").and_then(Value::as_str).map(|s| s.to_string()));
+        match raw {
+            Some(raw) => Ok(raw),
+            None => bail!(ErrorKind::MissingMail(uid)),
+        }
+    }
+
+    /// Get flags of mail
+    pub fn fetch_flags(&mut self, uid: u32) -> Result<Vec<String>> {
+        let lines = self.uid_fetch(&uid.to_string(), "(UID FLAGS)")?;
+        let fetch = match parse_fetch(&lines)?.into_iter().find(
+            |f| f.uid() == Some(uid),
+        ) {
+            Some(fetch) => fetch,
+            None => bail!(ErrorKind::MissingMail(uid)),
+        };
+        Ok(
+            fetch
+                .get("FLAGS")
+                .and_then(Value::as_list)
+                .map(|flags| {
+                    flags
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(|f| f.to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Get uids of all mails with an uid greater than given one
+    pub fn uids_after(&mut self, uid: u32) -> Result<Vec<u32>> {
+        let lines = self.uid_fetch(&format!("{}:*", uid + 1), "UID")?;
+        let mut uids: Vec<u32> = parse_fetch(&lines)?
+            .iter()
+            .filter_map(|f| f.uid())
+            .filter(|u| *u > uid)
+            .collect();
+        uids.sort();
+        Ok(uids)
+    }
+
+    /// Get uids of mails of selected mailbox matching search criteria, like `SINCE 1-Feb-2026`
+    pub fn uid_search(&mut self, criteria: &str) -> Result<Vec<u32>> {
+        let lines = self.run_command_and_read(&format!("UID SEARCH {}", criteria))?;
+        let mut uids = parse_search(&lines);
+        uids.sort();
+        Ok(uids)
+    }
+
+    /// Create a mailbox unless it exists, `delimiter` is the hierarchy delimiter of server
+    pub fn create(&mut self, mailbox_name: &str, delimiter: Option<&str>) -> Result<()> {
+        let (reference, name) = split_mailbox(mailbox_name, delimiter);
+        if !parse_list(&self.list(reference, name)?)?.is_empty() {
             return Ok(());
         }
-        match &mut self.0 {
-            &mut ConnectionResult::Normal(ref mut s) => {
-                s.create(mailbox_name).chain_err(|| "fail when creating")
+        let mailbox_name = quote(mailbox_name);
+        match self.client {
+            ConnectionResult::Normal(ref mut s) => {
+                s.create(&mailbox_name).chain_err(|| "fail when creating")
             }
-            &mut ConnectionResult::Secure(ref mut s) => {
-                s.create(mailbox_name).chain_err(|| "fail when creating")
+            ConnectionResult::Secure(ref mut s) => {
+                s.create(&mailbox_name).chain_err(|| "fail when creating")
             }
         }
     }
 
     /// Removes all messages that have the \Deleted flag
     pub fn expunge(&mut self) -> Result<()> {
-        match &mut self.0 {
-            &mut ConnectionResult::Normal(ref mut s) => {
+        match self.client {
+            ConnectionResult::Normal(ref mut s) => {
                 s.expunge().chain_err(|| "fail with expunge")
             }
-            &mut ConnectionResult::Secure(ref mut s) => {
+            ConnectionResult::Secure(ref mut s) => {
                 s.expunge().chain_err(|| "fail with expunge")
             }
         }
     }
 
-    /// Alters data with a message
-    pub fn store(&mut self, sequence_set: &str, query: &str) -> Result<Vec<String>> {
-        match &mut self.0 {
-            &mut ConnectionResult::Normal(ref mut s) => {
-                s.store(sequence_set, query).chain_err(|| "fail with store")
+    /// Alters data with a message using uids
+    pub fn uid_store(&mut self, uid_set: &str, query: &str) -> Result<Vec<String>> {
+        match self.client {
+            ConnectionResult::Normal(ref mut s) => {
+                s.uid_store(uid_set, query).chain_err(|| "fail with store")
             }
-            &mut ConnectionResult::Secure(ref mut s) => {
-                s.store(sequence_set, query).chain_err(|| "fail with store")
+            ConnectionResult::Secure(ref mut s) => {
+                s.uid_store(uid_set, query).chain_err(|| "fail with store")
             }
         }
     }
 
-    /// Copy message to mailbox
-    pub fn copy(&mut self, sequence_set: &str, mailbox_name: &str) -> Result<()> {
-        match &mut self.0 {
-            &mut ConnectionResult::Normal(ref mut s) => {
-                s.copy(sequence_set, mailbox_name).chain_err(
+    /// Copy message to mailbox using uids
+    pub fn uid_copy(&mut self, uid_set: &str, mailbox_name: &str) -> Result<()> {
+        match self.client {
+            ConnectionResult::Normal(ref mut s) => {
+                s.uid_copy(uid_set, mailbox_name).chain_err(
                     || "fail with copying",
                 )
             }
-            &mut ConnectionResult::Secure(ref mut s) => {
-                s.copy(sequence_set, mailbox_name).chain_err(
+            ConnectionResult::Secure(ref mut s) => {
+                s.uid_copy(uid_set, mailbox_name).chain_err(
                     || "fail with copying",
                 )
             }
@@ -156,29 +315,142 @@ impl Connection {
 
     /// List mails
     pub fn list(&mut self, ref_name: &str, mailbox_search_pattern: &str) -> Result<Vec<String>> {
-        match &mut self.0 {
-            &mut ConnectionResult::Normal(ref mut s) => {
-                s.list(ref_name, mailbox_search_pattern).chain_err(
-                    || "fail when getting list",
+        // imap sends arguments as is, an empty reference must be quoted
+        let (ref_name, pattern) = (quote(ref_name), quote(mailbox_search_pattern));
+        match self.client {
+            ConnectionResult::Normal(ref mut s) => {
+                s.list(&ref_name, &pattern).chain_err(|| "fail when getting list")
+            }
+            ConnectionResult::Secure(ref mut s) => {
+                s.list(&ref_name, &pattern).chain_err(|| "fail when getting list")
+            }
+        }
+    }
+
+    /// Find special mailboxes like trash, config overrides server attributes
+    pub fn discover_folders(&mut self, overrides: &Folders) -> Result<()> {
+        let lines = self.list("", "*")?;
+        self.folders = special_folders(&parse_list(&lines)?, overrides);
+        Ok(())
+    }
+
+    /// Get mailboxes matching one of the patterns, mailboxes which can't be selected are skipped
+    pub fn watched_mailboxes(&mut self, patterns: &[String]) -> Result<Vec<ListEntry>> {
+        let mut mailboxes: Vec<ListEntry> = Vec::new();
+        for pattern in patterns {
+            let lines = self.list("", pattern)?;
+            for entry in parse_list(&lines)? {
+                let selectable = !entry.attributes.iter().any(|a| {
+                    a.eq_ignore_ascii_case("\\Noselect") || a.eq_ignore_ascii_case("\\NonExistent")
+                });
+                if selectable && !mailboxes.iter().any(|m| m.name == entry.name) {
+                    mailboxes.push(entry);
+                }
+            }
+        }
+        Ok(mailboxes)
+    }
+
+    /// Get name of mailbox with this special use
+    pub fn special_folder(&self, special_use: SpecialUse) -> Option<&str> {
+        self.folders
+            .iter()
+            .find(|&&(u, _)| u == special_use)
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// Run a raw command, tagged response must be OK
+    pub fn run_command(&mut self, command: &str) -> Result<()> {
+        match self.client {
+            ConnectionResult::Normal(ref mut s) => {
+                s.run_command_and_check_ok(command).chain_err(
+                    || format!("fail with command {}", command),
+                )
+            }
+            ConnectionResult::Secure(ref mut s) => {
+                s.run_command_and_check_ok(command).chain_err(
+                    || format!("fail with command {}", command),
                 )
             }
-            &mut ConnectionResult::Secure(ref mut s) => {
-                s.list(ref_name, mailbox_search_pattern).chain_err(
-                    || "fail when getting list",
+        }
+    }
+
+    /// Run a raw command and get lines returned by server, tagged response must be OK
+    pub fn run_command_and_read(&mut self, command: &str) -> Result<Vec<String>> {
+        match self.client {
+            ConnectionResult::Normal(ref mut s) => {
+                s.run_command_and_read_response(command).chain_err(
+                    || format!("fail with command {}", command),
+                )
+            }
+            ConnectionResult::Secure(ref mut s) => {
+                s.run_command_and_read_response(command).chain_err(
+                    || format!("fail with command {}", command),
                 )
             }
         }
     }
 
+    /// Group mails in threads with THREAD extension, if server supports it (RFC 5256)
+    ///
+    /// Only given mails are threaded: the search criteria limit threading to them, mails processed
+    /// before join their thread through threads remembered in state, see `threading::identify`.
+    /// `None` is returned if server doesn't support threads by references.
+    pub fn thread_uids(&mut self, uids: &[u32]) -> Result<Option<Vec<Vec<u32>>>> {
+        if uids.is_empty() || !self.has_capability("THREAD=REFERENCES") {
+            return Ok(None);
+        }
+        let command = format!("UID THREAD REFERENCES UTF-8 UID {}", uid_set(uids));
+        let lines = self.run_command_and_read(&command)?;
+        Ok(Some(parse_thread(&lines)?))
+    }
+
+    /// Move mail to mailbox, with MOVE extension if server supports it (RFC 6851)
+    ///
+    /// Otherwise mail is copied and marked as deleted, see `delete_mail`.
+    pub fn move_mail(&mut self, uid: u32, mailbox_name: &str) -> Result<()> {
+        if self.has_capability("MOVE") {
+            self.run_command(&format!("UID MOVE {} {}", uid, quote(mailbox_name)))
+        } else {
+            self.uid_copy(&uid.to_string(), mailbox_name)?;
+            self.delete_mail(uid)
+        }
+    }
+
+    /// Mark mail as deleted, it will be removed by next call to `expunge_deleted`
+    pub fn delete_mail(&mut self, uid: u32) -> Result<()> {
+        self.uid_store(&uid.to_string(), "+FLAGS.SILENT (\\Deleted)")?;
+        self.deleted.push(uid);
+        Ok(())
+    }
+
+    /// Remove mails deleted with `delete_mail`
+    ///
+    /// When server supports UIDPLUS (RFC 4315) only these mails are removed, otherwise every
+    /// mail marked as deleted in mailbox is.
+    pub fn expunge_deleted(&mut self) -> Result<()> {
+        if self.deleted.is_empty() {
+            return Ok(());
+        }
+        if self.has_capability("UIDPLUS") {
+            let command = format!("UID EXPUNGE {}", uid_set(&self.deleted));
+            self.run_command(&command)?;
+        } else {
+            self.expunge()?;
+        }
+        self.deleted.clear();
+        Ok(())
+    }
+
     /// Get status of mailbox
     pub fn status(&mut self, mailbox_name: &str, status_data_items: &str) -> Result<Vec<String>> {
-        match &mut self.0 {
-            &mut ConnectionResult::Normal(ref mut s) => {
+        match self.client {
+            ConnectionResult::Normal(ref mut s) => {
                 s.status(mailbox_name, status_data_items).chain_err(
                     || "fail when getting status",
                 )
             }
-            &mut ConnectionResult::Secure(ref mut s) => {
+            ConnectionResult::Secure(ref mut s) => {
                 s.status(mailbox_name, status_data_items).chain_err(
                     || "fail when getting status",
                 )
@@ -188,30 +460,295 @@ impl Connection {
 
     /// Send noop
     pub fn noop(&mut self) -> Result<()> {
-        match &mut self.0 {
-            &mut ConnectionResult::Normal(ref mut s) => s.noop().chain_err(|| "fail with noop"),
-            &mut ConnectionResult::Secure(ref mut s) => s.noop().chain_err(|| "fail with noop"),
+        match self.client {
+            ConnectionResult::Normal(ref mut s) => s.noop().chain_err(|| "fail with noop"),
+            ConnectionResult::Secure(ref mut s) => s.noop().chain_err(|| "fail with noop"),
         }
     }
 
-    /// Get number of mails
-    pub fn mail_number(&mut self, mailbox_name: &str) -> Result<usize> {
-        let status = self.status(mailbox_name, "(messages)")?;
-        let num = status[0]
-            .matches(char::is_numeric)
-            .map(|c| c)
-            .collect::<String>();
-        num.parse::<usize>().chain_err(
-            || "fail parsing number of mails",
-        )
+    /// Request capabilities of server, imap already splits them in words
+    pub fn capability(&mut self) -> Result<Vec<String>> {
+        let capabilities = match self.client {
+            ConnectionResult::Normal(ref mut s) => {
+                s.capability().chain_err(|| "fail when getting capabilities")?
+            }
+            ConnectionResult::Secure(ref mut s) => {
+                s.capability().chain_err(|| "fail when getting capabilities")?
+            }
+        };
+        Ok(capabilities.iter().map(|c| c.to_uppercase()).collect())
+    }
+
+    /// Check if server advertised capability at login
+    pub fn has_capability(&self, capability: &str) -> bool {
+        let capability = capability.to_uppercase();
+        self.capabilities.contains(&capability)
+    }
+
+    /// Wait until server notify a change in selected mailbox or timeout expires
+    ///
+    /// Timeout should stay below 29 minutes, servers may close connections idle for longer
+    /// (RFC 2177).
+    ///
+    /// imap panics when IDLE can't be ended, like when server closed connection while waiting.
+    /// This panic is caught and given as a connection error, the connection can't be used anymore.
+    pub fn idle(&mut self, timeout: Duration) -> Result<()> {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| match self.client {
+            ConnectionResult::Normal(ref mut s) => {
+                s.idle().and_then(|mut handle| handle.wait_timeout(timeout))
+            }
+            ConnectionResult::Secure(ref mut s) => {
+                s.idle().and_then(|mut handle| handle.wait_timeout(timeout))
+            }
+        }));
+        let result = match result {
+            Ok(result) => result,
+            Err(_) => bail!(ErrorKind::ConnectionLost("IDLE did not terminate".to_string())),
+        };
+        match result {
+            Err(::imap::error::Error::Io(ref e))
+                if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => {
+                Ok(())
+            }
+            result => result.chain_err(|| "fail with idle"),
+        }
+    }
+
+    /// Get uid validity of mailbox and uid the next mail will get
+    pub fn uid_status(&mut self, mailbox_name: &str) -> Result<(u32, u32)> {
+        let status = self.status(mailbox_name, "(UIDVALIDITY UIDNEXT)")?;
+        let line = status.first().map(String::as_str).unwrap_or_default();
+        match (status_value(line, "UIDVALIDITY"), status_value(line, "UIDNEXT")) {
+            (Some(uid_validity), Some(uid_next)) => Ok((uid_validity, uid_next)),
+            _ => bail!(ErrorKind::InvalidResponse(line.to_string())),
+        }
+    }
+}
+
+/// Split mailbox name into its parent, ending with hierarchy delimiter, and its last name
+///
+/// For example `Lists.rust` gives `Lists.` and `rust` when delimiter is `.`.
+fn split_mailbox<'a>(mailbox_name: &'a str, delimiter: Option<&str>) -> (&'a str, &'a str) {
+    let delimiter = match delimiter {
+        Some(delimiter) if !delimiter.is_empty() => delimiter,
+        _ => return ("", mailbox_name),
+    };
+    match mailbox_name.rfind(delimiter) {
+        Some(idx) => mailbox_name.split_at(idx + delimiter.len()),
+        None => ("", mailbox_name),
+    }
+}
+
+/// Read value of an item in a status response, like `UIDNEXT` in
+/// `* STATUS INBOX (UIDNEXT 42 UIDVALIDITY 7)`
+fn status_value(line: &str, item: &str) -> Option<u32> {
+    let items = &line[line.rfind('(')? + 1..];
+    let mut words = items.trim_end().trim_end_matches(')').split_whitespace();
+    while let Some(word) = words.next() {
+        let value = words.next()?;
+        if word.eq_ignore_ascii_case(item) {
+            return value.parse().ok();
+        }
+    }
+    None
+}
+
+/// Format uids as an imap sequence set, consecutive uids are merged into ranges
+fn uid_set(uids: &[u32]) -> String {
+    let mut uids = uids.to_vec();
+    uids.sort();
+    uids.dedup();
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for uid in uids {
+        match ranges.last_mut() {
+            Some(&mut (_, ref mut end)) if *end + 1 == uid => {
+                *end = uid;
+                continue;
+            }
+            _ => {}
+        }
+        ranges.push((uid, uid));
+    }
+    ranges
+        .iter()
+        .map(|&(start, end)| if start == end {
+            start.to_string()
+        } else {
+            format!("{}:{}", start, end)
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Sections of text bodies and uids of mails having their body in each section
+///
+/// Bodies which can't be read by imap are skipped, their content stays empty.
+fn body_sections(mails: &[Mail]) -> Vec<(String, Vec<u32>)> {
+    let mut sections: Vec<(String, Vec<u32>)> = Vec::new();
+    for mail in mails {
+        let section = match mail.structure.as_ref().and_then(Part::text_body) {
+            Some((section, part)) if part.is_fetchable() => section,
+            _ => continue,
+        };
+        match sections.iter().position(|(s, _)| *s == section) {
+            Some(idx) => sections[idx].1.push(mail.uid),
+            None => sections.push((section, vec![mail.uid])),
+        }
     }
+    sections
+}
+
+/// List of header names to fetch, usual headers followed by given ones
+fn header_fields(headers: &[String]) -> String {
+    let mut fields: Vec<String> = FETCHED_HEADERS.iter().map(|h| h.to_string()).collect();
+    for header in headers {
+        let header = header.to_uppercase();
+        if !fields.contains(&header) {
+            fields.push(header);
+        }
+    }
+    fields.join(" ")
+}
+
+/// Quote a string to use it in a command
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
 }
 
 impl Drop for Connection {
     fn drop(&mut self) {
-        let _ = match &mut self.0 {
-            &mut ConnectionResult::Normal(ref mut s) => s.logout(),
-            &mut ConnectionResult::Secure(ref mut s) => s.logout(),
+        let _ = match self.client {
+            ConnectionResult::Normal(ref mut s) => s.logout(),
+            ConnectionResult::Secure(ref mut s) => s.logout(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::{body_sections, header_fields, quote, split_mailbox, status_value, uid_set,
+                Connection, ConnectionResult};
+    use error::ErrorKind;
+    use imap::client::Client;
+    use mail::Mail;
+    use mime::Part;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Fake imap server closing the connection once IDLE is started
+    fn stand_in() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            writer.write_all(b"* OK ready\r\n").unwrap();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let tag = line.split_whitespace().next().unwrap_or_default().to_string();
+                if line.contains("IDLE") {
+                    writer.write_all(b"+ idling\r\n").unwrap();
+                    break;
+                }
+                let reply = format!("* CAPABILITY IMAP4rev1 IDLE\r\n{} OK done\r\n", tag);
+                writer.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn connection_idle_lost() {
+        let client = Client::connect(("127.0.0.1", stand_in())).unwrap();
+        let mut connection = Connection::new(ConnectionResult::Normal(client)).unwrap();
+        assert!(connection.has_capability("idle"));
+        let error = connection.idle(Duration::from_secs(5)).unwrap_err();
+        assert!(matches!(*error.kind(), ErrorKind::ConnectionLost(_)));
+        assert!(error.is_connection());
+    }
+
+    #[test]
+    fn connection_split_mailbox() {
+        assert_eq!(split_mailbox("Lists.rust", Some(".")), ("Lists.", "rust"));
+        assert_eq!(split_mailbox("Lists.rust.users", Some(".")), ("Lists.rust.", "users"));
+        assert_eq!(split_mailbox("Lists/rust", Some(".")), ("", "Lists/rust"));
+        assert_eq!(split_mailbox("Lists/rust", Some("/")), ("Lists/", "rust"));
+        assert_eq!(split_mailbox("Archive", Some(".")), ("", "Archive"));
+        assert_eq!(split_mailbox("Lists.rust", None), ("", "Lists.rust"));
+    }
+
+    #[test]
+    fn uid_set_ranges() {
+        assert_eq!(uid_set(&[4]), "4");
+        assert_eq!(uid_set(&[1, 2, 3, 7, 9, 10]), "1:3,7,9:10");
+        assert_eq!(uid_set(&[5, 3, 4, 4]), "3:5");
+    }
+
+    #[test]
+    fn status_values() {
+        let line = "* STATUS \"Lists (old)\" (UIDNEXT 42 UIDVALIDITY 7)\r\n";
+        assert_eq!(status_value(line, "UIDVALIDITY"), Some(7));
+        assert_eq!(status_value(line, "uidnext"), Some(42));
+        assert_eq!(status_value(line, "MESSAGES"), None);
+        assert_eq!(status_value("* STATUS INBOX", "UIDNEXT"), None);
+    }
+
+    #[test]
+    fn quote_mailbox() {
+        assert_eq!(quote("INBOX"), "\"INBOX\"");
+        assert_eq!(quote("[Gmail]/All Mail"), "\"[Gmail]/All Mail\"");
+        assert_eq!(quote("a\"b\\c"), "\"a\\\"b\\\\c\"");
+    }
+
+    #[test]
+    fn header_fields_extra() {
+        let fields = header_fields(&["X-Spam-Flag".to_string(), "list-id".to_string()]);
+        assert!(fields.starts_with("FROM TO CC SUBJECT "));
+        assert!(fields.ends_with(" LIST-ID LIST-POST LIST-UNSUBSCRIBE X-SPAM-FLAG"));
+    }
+
+    #[test]
+    fn body_sections_grouped() {
+        let text = |mime_type: &str| {
+            Part {
+                mime_type: mime_type.to_string(),
+                ..Part::default()
+            }
+        };
+        let mail = |uid, structure| {
+            let mut mail = Mail::parse_fetched(vec!["Subject: Hehe\r\n\r\n".to_string()]).unwrap();
+            mail.uid = uid;
+            mail.structure = structure;
+            mail
+        };
+        let alternative = Part {
+            mime_type: "multipart/alternative".to_string(),
+            parts: vec![text("text/html"), text("text/plain")],
+            ..Part::default()
+        };
+        let latin1 = Part {
+            encoding: "8bit".to_string(),
+            params: vec![("charset".to_string(), "ISO-8859-1".to_string())],
+            ..text("text/plain")
         };
+        let mails = vec![
+            mail(1, Some(text("text/plain"))),
+            mail(2, Some(alternative.clone())),
+            mail(3, None),
+            mail(4, Some(text("image/png"))),
+            mail(5, Some(text("text/html"))),
+            mail(6, Some(alternative)),
+            mail(7, Some(latin1)),
+        ];
+        assert_eq!(
+            body_sections(&mails),
+            vec![("1".to_string(), vec![1, 5]), ("2".to_string(), vec![2, 6])]
+        );
     }
 }
@@ -0,0 +1,206 @@
+use encoding::DecoderTrap;
+use encoding::label::encoding_from_whatwg_label;
+use unicode_normalization::UnicodeNormalization;
+
+/// Compose Unicode text (NFC), so texts written differently compare equal
+pub fn normalize(text: &str) -> String {
+    text.nfc().collect()
+}
+
+/// Decode text in charset, unknown charsets are read as UTF-8
+///
+/// Labels are read like browsers do, so `iso-8859-1` and `us-ascii` are windows-1252.
+pub fn decode_charset(bytes: &[u8], charset: &str) -> String {
+    match encoding_from_whatwg_label(charset.trim()) {
+        Some(encoding) => {
+            match encoding.decode(bytes, DecoderTrap::Replace) {
+                Ok(text) => text,
+                Err(_) => String::from_utf8_lossy(bytes).into_owned(),
+            }
+        }
+        None => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// Decode base64, whitespace is ignored and missing padding is tolerated
+fn decode_base64(text: &str) -> Vec<u8> {
+    let mut text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let trimmed = text.trim_end_matches('=').len();
+    text.truncate(trimmed);
+    while !text.len().is_multiple_of(4) {
+        text.push('=');
+    }
+    ::base64::decode(&text).unwrap_or_default()
+}
+
+/// Decode quoted-printable, in headers `_` is a space (RFC 2047 Q encoding)
+fn decode_quoted_printable(text: &str, header: bool) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::new();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'=' if bytes[idx + 1..].starts_with(b"\r\n") => idx += 3,
+            b'=' if bytes[idx + 1..].starts_with(b"\n") => idx += 2,
+            b'=' if idx + 2 < bytes.len() => {
+                let high = (bytes[idx + 1] as char).to_digit(16);
+                let low = (bytes[idx + 2] as char).to_digit(16);
+                match (high, low) {
+                    (Some(high), Some(low)) => {
+                        decoded.push((high * 16 + low) as u8);
+                        idx += 3;
+                    }
+                    _ => {
+                        decoded.push(b'=');
+                        idx += 1;
+                    }
+                }
+            }
+            b'_' if header => {
+                decoded.push(b' ');
+                idx += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                idx += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Decode body of a part with its transfer encoding and charset, result is normalized
+///
+/// Without charset, text is read as UTF-8. Note that imap 0.4 reads every response line as UTF-8
+/// and panics on other bytes, so 8bit parts in other charsets can't be fetched from imap, see
+/// `Part::is_fetchable`.
+pub fn decode_body(body: &[u8], transfer_encoding: &str, charset: &str) -> String {
+    let bytes = match transfer_encoding.trim().to_lowercase().as_str() {
+        "base64" => decode_base64(&String::from_utf8_lossy(body)),
+        "quoted-printable" => decode_quoted_printable(&String::from_utf8_lossy(body), false),
+        _ => body.to_vec(),
+    };
+    normalize(&decode_charset(&bytes, charset))
+}
+
+/// Read encoded word at start of text, like `=?UTF-8?Q?caf=C3=A9?=`
+///
+/// Return its charset, its decoded bytes and its length in text.
+fn encoded_word(text: &str) -> Option<(String, Vec<u8>, usize)> {
+    if !text.starts_with("=?") {
+        return None;
+    }
+    let mut fields = text[2..].splitn(3, '?');
+    let charset = match fields.next() {
+        Some(charset) if !charset.is_empty() => charset,
+        _ => return None,
+    };
+    let encoding = fields.next().unwrap_or_default();
+    let rest = fields.next().unwrap_or_default();
+    let end = rest.find("?=")?;
+    let encoded = &rest[..end];
+    if encoded.contains(char::is_whitespace) || charset.contains(char::is_whitespace) {
+        return None;
+    }
+    let bytes = match encoding {
+        "B" | "b" => decode_base64(encoded),
+        "Q" | "q" => decode_quoted_printable(encoded, true),
+        _ => return None,
+    };
+    let len = 2 + charset.len() + 1 + encoding.len() + 1 + end + 2;
+    // Language like in `=?UTF-8*fr?Q?...?=` is dropped (RFC 2231)
+    let charset = charset.split('*').next().unwrap_or_default().to_lowercase();
+    Some((charset, bytes, len))
+}
+
+/// Decode encoded words of a header value (RFC 2047), like `=?ISO-8859-1?Q?Gr=FC=DFe?=`
+///
+/// Whitespace between encoded words is dropped and adjacent words in the same charset are
+/// decoded together, since a character can be split between them. Result is normalized.
+pub fn decode_words(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut pending: Option<(String, Vec<u8>)> = None;
+    let mut rest = value;
+    while let Some(idx) = rest.find("=?") {
+        let word = match encoded_word(&rest[idx..]) {
+            Some(word) => word,
+            None => {
+                if let Some((charset, bytes)) = pending.take() {
+                    decoded.push_str(&decode_charset(&bytes, &charset));
+                }
+                decoded.push_str(&rest[..idx + 2]);
+                rest = &rest[idx + 2..];
+                continue;
+            }
+        };
+        let (charset, bytes, len) = word;
+        let adjacent = pending.is_some() && rest[..idx].trim().is_empty();
+        let same_charset = match pending {
+            Some((ref pending_charset, _)) => *pending_charset == charset,
+            None => false,
+        };
+        if adjacent && same_charset {
+            if let Some((_, ref mut pending_bytes)) = pending {
+                pending_bytes.extend(bytes);
+            }
+        } else {
+            if let Some((pending_charset, pending_bytes)) = pending.take() {
+                decoded.push_str(&decode_charset(&pending_bytes, &pending_charset));
+            }
+            if !adjacent {
+                decoded.push_str(&rest[..idx]);
+            }
+            pending = Some((charset, bytes));
+        }
+        rest = &rest[idx + len..];
+    }
+    if let Some((charset, bytes)) = pending {
+        decoded.push_str(&decode_charset(&bytes, &charset));
+    }
+    decoded.push_str(rest);
+    normalize(&decoded)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn decode_words_examples() {
+        let cases = [
+            ("=?UTF-8?B?UmVjaG51bmc=?=", "Rechnung"),
+            ("=?ISO-8859-1?Q?Gr=FC=DFe_aus_K=F6ln?=", "Grüße aus Köln"),
+            ("Re: =?utf-8?q?caf=C3=A9?= ouvert", "Re: café ouvert"),
+            ("=?UTF-8?Q?a?= =?UTF-8?Q?b?=", "ab"),
+            ("=?UTF-8?Q?a?=\r\n =?ISO-8859-1?Q?=E9?=", "aé"),
+            ("=?UTF-8?Q?a?= b =?UTF-8?Q?c?=", "a b c"),
+            // Character split between two words
+            ("=?UTF-8?B?w6k=?= =?UTF-8?B?w6k?=", "éé"),
+            ("=?UTF-8?Q?=C3?= =?UTF-8?Q?=A9?=", "é"),
+            ("=?Shift_JIS?B?g2WDWINn?=", "テスト"),
+            ("=?UTF-8*fr?Q?=C3=A9t=C3=A9?=", "été"),
+            ("=?unknown?Q?caf=C3=A9?=", "café"),
+            ("1 + 1 =? 2", "1 + 1 =? 2"),
+            ("=?UTF-8?X?abc?= =?UTF-8?Q?not closed", "=?UTF-8?X?abc?= =?UTF-8?Q?not closed"),
+            // Decomposed é is composed
+            ("=?UTF-8?Q?e=CC=81?=", "é"),
+        ];
+        for &(value, expected) in &cases {
+            assert_eq!(decode_words(value), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn decode_body_encodings() {
+        assert_eq!(decode_body(b"R3L832U=", "BASE64", "iso-8859-1"), "Grüße");
+        assert_eq!(decode_body(b"R3L8\r\n32U", "base64", "iso-8859-1"), "Grüße");
+        assert_eq!(
+            decode_body(b"caf=C3=A9 =\r\nsoft break_=3D", "quoted-printable", "utf-8"),
+            "café soft break_="
+        );
+        assert_eq!(decode_body(b"d\xe9j\xe0", "8bit", "iso-8859-1"), "déjà");
+        assert_eq!(decode_body("déjà".as_bytes(), "8bit", ""), "déjà");
+        assert_eq!(decode_body("Cafe\u{301}".as_bytes(), "", ""), "Café");
+        assert_eq!(decode_body(b"=E9", "quoted-printable", "windows-1252"), "é");
+    }
+}
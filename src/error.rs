@@ -1,5 +1,8 @@
+// error_chain 0.10 implements `description` and `cause`, deprecated by newer compilers
+#![allow(deprecated)]
 
-const MISSING_ACCOUNT_ERR: &'static str = "Account field is missing from config file, \
+
+const MISSING_ACCOUNT_ERR: &str = "Account field is missing from config file, \
                 to fix this error please add these lines at the start \
                 into your config file:\n\
                 [account]\n\
@@ -13,9 +16,9 @@ error_chain! {
     foreign_links {
         Io(::std::io::Error);
         Imap(::imap::error::Error);
-        MailParse(::mailparse::MailParseError);
         OpensslStack(::openssl::error::ErrorStack);
         TomlDe(::toml::de::Error);
+        TomlSer(::toml::ser::Error);
     }
 
     errors {
@@ -31,9 +34,131 @@ error_chain! {
             description("given condition checker is invalid")
             display("checker `{}` is invalid", checker)
         }
+        InvalidRegex(regex: String, error: String) {
+            description("given regex is invalid")
+            display("regex `{}` is invalid: {}", regex, error)
+        }
+        InvalidGlob(glob: String, error: String) {
+            description("given glob is invalid")
+            display("glob `{}` is invalid: {}", glob, error)
+        }
+        InvalidTimezone(timezone: String) {
+            description("given timezone is invalid")
+            display("timezone `{}` is invalid", timezone)
+        }
+        InvalidExpression(expr: String, reason: String) {
+            description("given condition expression is invalid")
+            display("expression `{}` is invalid: {}", expr, reason)
+        }
+        MissingConditions {
+            description("rule has no conditions")
+            display("rule has no conditions, use `match = \"all\"` to apply it to every mail")
+        }
+        ConnectionLost(reason: String) {
+            description("connection to server was lost")
+            display("connection lost: {}", reason)
+        }
+        InvalidResponse(response: String) {
+            description("server response is invalid")
+            display("response `{}` is invalid", response)
+        }
+        MissingFolder(folder: String) {
+            description("no mailbox has this special use")
+            display("no mailbox is marked as `{}`, set it in [folders] section", folder)
+        }
+        MissingRuleFolder(rule: String, folder: String) {
+            description("a rule uses a special use no mailbox has")
+            display("rule `{}` uses `{}` but no mailbox is marked with it, set it in [folders] section",
+                    rule, folder)
+        }
+        MissingMail(uid: u32) {
+            description("mail is missing from mailbox")
+            display("mail with uid {} is missing from mailbox", uid)
+        }
+        UnfetchableMail(uid: u32) {
+            description("mail can't be fetched from server")
+            display("mail with uid {} has 8bit or binary parts which can't be fetched", uid)
+        }
+        MissingSmtp {
+            description("no smtp field in configuration file")
+            display("an [smtp] section is required in configuration file to send mails")
+        }
+        Smtp(reply: String) {
+            description("smtp server returned an error")
+            display("smtp server replied `{}`", reply)
+        }
+        SmtpConnection(reason: String) {
+            description("connection to smtp server failed")
+            display("connection to smtp server failed: {}", reason)
+        }
+        SmtpUnsupported(extension: String) {
+            description("smtp server doesn't support an extension needed to send mail")
+            display("smtp server doesn't support {}, needed to send this mail", extension)
+        }
         MissingAccount {
             description("no account field in configuration file")
             display("{}", MISSING_ACCOUNT_ERR)
         }
     }
 }
+
+/// Check if imap error comes from the network rather than from the server
+fn is_imap_connection(error: &::imap::error::Error) -> bool {
+    matches!(
+        *error,
+        ::imap::error::Error::Io(_) | ::imap::error::Error::Ssl(_) |
+            ::imap::error::Error::ConnectionLost
+    )
+}
+
+impl Error {
+    /// Check if error comes from a lost or failed imap connection
+    ///
+    /// These errors are temporary, the connection is retried. Other errors are refused commands,
+    /// invalid data or local errors (like state file which can't be saved), retrying would give the
+    /// same result.
+    pub fn is_connection(&self) -> bool {
+        match *self.kind() {
+            ErrorKind::Imap(ref e) => return is_imap_connection(e),
+            ErrorKind::ConnectionLost(_) => return true,
+            _ => {}
+        }
+        // Errors given to `chain_err` are kept as is
+        match self.1.next_error {
+            Some(ref next) => {
+                if let Some(e) = next.downcast_ref::<Error>() {
+                    e.is_connection()
+                } else if let Some(e) = next.downcast_ref::<::imap::error::Error>() {
+                    is_imap_connection(e)
+                } else {
+                    false
+                }
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn error_is_connection() {
+        let io_error = || io::Error::new(io::ErrorKind::ConnectionReset, "reset");
+        // Local io errors, like a state file which can't be written, aren't connection errors
+        assert!(!Error::from(io_error()).is_connection());
+        let saved: Result<()> = Err(io_error()).chain_err(|| "fail when saving state");
+        assert!(!saved.unwrap_err().is_connection());
+        assert!(Error::from(ErrorKind::ConnectionLost("closed".to_string())).is_connection());
+        assert!(Error::from(::imap::error::Error::ConnectionLost).is_connection());
+        let chained: Result<()> = Err(::imap::error::Error::Io(io_error())).chain_err(|| "fail");
+        assert!(chained.unwrap_err().is_connection());
+        let refused: Result<()> = Err(::imap::error::Error::NoResponse(vec![]))
+            .chain_err(|| "fail when login");
+        assert!(!refused.unwrap_err().is_connection());
+        assert!(!Error::from(ErrorKind::MissingFolder("Trash".to_string())).is_connection());
+        assert!(!Error::from(ErrorKind::Smtp("550 no such user".to_string())).is_connection());
+    }
+}
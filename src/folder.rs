@@ -0,0 +1,236 @@
+use error::*;
+use response::ListEntry;
+use toml::Value;
+
+/// Special use of a mailbox, as advertised by server (RFC 6154)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpecialUse {
+    Trash,
+    Junk,
+    Archive,
+    Sent,
+    Flagged,
+    Important,
+}
+
+impl SpecialUse {
+    /// Every special use
+    pub fn all() -> [SpecialUse; 6] {
+        [
+            SpecialUse::Trash,
+            SpecialUse::Junk,
+            SpecialUse::Archive,
+            SpecialUse::Sent,
+            SpecialUse::Flagged,
+            SpecialUse::Important,
+        ]
+    }
+
+    /// Mailbox attribute marking this special use
+    pub fn attribute(&self) -> &'static str {
+        match *self {
+            SpecialUse::Trash => "\\Trash",
+            SpecialUse::Junk => "\\Junk",
+            SpecialUse::Archive => "\\Archive",
+            SpecialUse::Sent => "\\Sent",
+            SpecialUse::Flagged => "\\Flagged",
+            SpecialUse::Important => "\\Important",
+        }
+    }
+
+    /// Get special use from its attribute, case insensitive
+    pub fn from_attribute(attribute: &str) -> Option<SpecialUse> {
+        SpecialUse::all().iter().cloned().find(|u| {
+            u.attribute().eq_ignore_ascii_case(attribute)
+        })
+    }
+}
+
+/// Mailbox names given in config, overriding the discovered ones
+#[derive(Debug, Default, Deserialize)]
+pub struct Folders {
+    pub trash: Option<String>,
+    pub junk: Option<String>,
+    pub archive: Option<String>,
+    pub sent: Option<String>,
+    pub flagged: Option<String>,
+    pub important: Option<String>,
+}
+
+impl Folders {
+    /// Convert toml table into Folders
+    pub fn from_toml(toml: &Value) -> Result<Folders> {
+        toml.clone().try_into().map_err(|e| e.into())
+    }
+
+    /// Get mailbox name given in config for this special use
+    pub fn get(&self, special_use: SpecialUse) -> Option<&str> {
+        let name = match special_use {
+            SpecialUse::Trash => &self.trash,
+            SpecialUse::Junk => &self.junk,
+            SpecialUse::Archive => &self.archive,
+            SpecialUse::Sent => &self.sent,
+            SpecialUse::Flagged => &self.flagged,
+            SpecialUse::Important => &self.important,
+        };
+        name.as_ref().map(|n| n.as_str())
+    }
+}
+
+/// Write INBOX in capitals at start of mailbox name, it is case insensitive (RFC 3501 section 5.1)
+fn canonical_inbox(name: &str, delimiter: Option<&str>) -> String {
+    match name.get(..5) {
+        Some(inbox) if inbox.eq_ignore_ascii_case("INBOX") => {
+            let rest = &name[5..];
+            if rest.is_empty() || delimiter.map(|d| rest.starts_with(d)) == Some(true) {
+                return format!("INBOX{}", rest);
+            }
+            name.to_string()
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// Check if both names are the same mailbox, INBOX being case insensitive
+pub fn same_mailbox(name: &str, other: &str, delimiter: Option<&str>) -> bool {
+    canonical_inbox(name, delimiter) == canonical_inbox(other, delimiter)
+}
+
+/// Check if mailbox name matches a list pattern
+///
+/// Like in imap LIST command, `*` matches anything and `%` matches anything but hierarchy delimiter.
+/// INBOX matches whatever its case.
+pub fn matches(pattern: &str, name: &str, delimiter: Option<&str>) -> bool {
+    matches_from(
+        &canonical_inbox(pattern, delimiter),
+        &canonical_inbox(name, delimiter),
+        delimiter,
+    )
+}
+
+fn matches_from(pattern: &str, name: &str, delimiter: Option<&str>) -> bool {
+    let mut pattern_chars = pattern.chars();
+    match pattern_chars.next() {
+        None => name.is_empty(),
+        Some(wildcard @ '*') |
+        Some(wildcard @ '%') => {
+            let rest = pattern_chars.as_str();
+            for (idx, c) in name.char_indices() {
+                if matches_from(rest, &name[idx..], delimiter) {
+                    return true;
+                }
+                if wildcard == '%' && delimiter.map(|d| d.starts_with(c)) == Some(true) {
+                    return false;
+                }
+            }
+            matches_from(rest, "", delimiter)
+        }
+        Some(p) => {
+            let mut name_chars = name.chars();
+            match name_chars.next() {
+                Some(n) if n == p => {
+                    matches_from(pattern_chars.as_str(), name_chars.as_str(), delimiter)
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+/// Find mailbox of each special use, from list response and config
+pub fn special_folders(
+    entries: &[ListEntry],
+    overrides: &Folders,
+) -> Vec<(SpecialUse, String)> {
+    let mut folders = Vec::new();
+    for special_use in &SpecialUse::all() {
+        let name = match overrides.get(*special_use) {
+            Some(name) => Some(name.to_string()),
+            None => {
+                entries
+                    .iter()
+                    .find(|e| {
+                        e.attributes.iter().any(|a| {
+                            SpecialUse::from_attribute(a) == Some(*special_use)
+                        })
+                    })
+                    .map(|e| e.name.clone())
+            }
+        };
+        if let Some(name) = name {
+            folders.push((*special_use, name));
+        }
+    }
+    folders
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn entry(name: &str, attributes: &[&str]) -> ListEntry {
+        ListEntry {
+            attributes: attributes.iter().map(|a| a.to_string()).collect(),
+            delimiter: Some("/".to_string()),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn folder_special_use() {
+        assert_eq!(
+            SpecialUse::from_attribute("\\trash"),
+            Some(SpecialUse::Trash)
+        );
+        assert_eq!(SpecialUse::from_attribute("\\HasNoChildren"), None);
+    }
+
+    #[test]
+    fn folder_matches() {
+        assert!(matches("INBOX", "INBOX", Some("/")));
+        assert!(!matches("INBOX", "INBOX/sub", Some("/")));
+        assert!(matches("Lists/*", "Lists/rust", Some("/")));
+        assert!(matches("Lists/*", "Lists/rust/users", Some("/")));
+        assert!(matches("Lists/%", "Lists/rust", Some("/")));
+        assert!(!matches("Lists/%", "Lists/rust/users", Some("/")));
+        assert!(matches("*", "INBOX", None));
+        assert!(matches("%s", "Lists", Some(".")));
+        assert!(!matches("Lists/*", "Spam", Some("/")));
+        assert!(matches("INBOX", "Inbox", Some("/")));
+        assert!(matches("inbox/*", "INBOX/sub", Some("/")));
+        assert!(matches("INBOX.%", "inbox.sub", Some(".")));
+        assert!(!matches("INBOX", "INBOXES", Some("/")));
+        assert!(!matches("Lists/inbox", "Lists/INBOX", Some("/")));
+    }
+
+    #[test]
+    fn folder_same_mailbox() {
+        assert!(same_mailbox("INBOX", "inbox", Some("/")));
+        assert!(same_mailbox("Inbox.Archive", "INBOX.Archive", Some(".")));
+        assert!(same_mailbox("Archive", "Archive", None));
+        assert!(!same_mailbox("Archive", "archive", Some("/")));
+        assert!(!same_mailbox("Archive/*", "Archive/2026", Some("/")));
+    }
+
+    #[test]
+    fn folder_discovery() {
+        let entries = vec![
+            entry("INBOX", &["\\HasNoChildren"]),
+            entry("[Gmail]/Corbeille", &["\\HasNoChildren", "\\Trash"]),
+            entry("[Gmail]/Important", &["\\HasNoChildren", "\\Important"]),
+            entry("[Gmail]/Spam", &["\\HasNoChildren", "\\Junk"]),
+        ];
+        let overrides = Folders {
+            junk: Some("Junk".to_string()),
+            ..Folders::default()
+        };
+        assert_eq!(
+            special_folders(&entries, &overrides),
+            vec![
+                (SpecialUse::Trash, "[Gmail]/Corbeille".to_string()),
+                (SpecialUse::Junk, "Junk".to_string()),
+                (SpecialUse::Important, "[Gmail]/Important".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,233 @@
+/// Tags starting a new line in text
+const BLOCK_TAGS: [&str; 21] = [
+    "address",
+    "article",
+    "blockquote",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "p",
+    "section",
+    "table",
+    "tr",
+];
+
+/// Tags whose content is never displayed
+const HIDDEN_TAGS: [&str; 4] = ["head", "script", "style", "title"];
+
+/// Named character references, most used ones only
+const ENTITIES: [(&str, &str); 32] = [
+    ("amp", "&"),
+    ("lt", "<"),
+    ("gt", ">"),
+    ("quot", "\""),
+    ("apos", "'"),
+    ("nbsp", "\u{a0}"),
+    ("copy", "©"),
+    ("reg", "®"),
+    ("trade", "™"),
+    ("euro", "€"),
+    ("pound", "£"),
+    ("deg", "°"),
+    ("times", "×"),
+    ("hellip", "…"),
+    ("ndash", "–"),
+    ("mdash", "—"),
+    ("lsquo", "‘"),
+    ("rsquo", "’"),
+    ("ldquo", "“"),
+    ("rdquo", "”"),
+    ("laquo", "«"),
+    ("raquo", "»"),
+    ("bull", "•"),
+    ("middot", "·"),
+    ("eacute", "é"),
+    ("egrave", "è"),
+    ("agrave", "à"),
+    ("ccedil", "ç"),
+    ("auml", "ä"),
+    ("ouml", "ö"),
+    ("uuml", "ü"),
+    ("szlig", "ß"),
+];
+
+/// Replace character reference at start of text, like `&amp;` or `&#233;`
+///
+/// Return replacement and length of reference, unknown references are kept as is.
+fn entity(text: &str) -> Option<(String, usize)> {
+    let end = match text.find(';') {
+        Some(end) if end > 1 && end <= 10 => end,
+        _ => return None,
+    };
+    let name = &text[1..end];
+    let replacement = if name.starts_with("#x") || name.starts_with("#X") {
+        u32::from_str_radix(&name[2..], 16).ok().and_then(::std::char::from_u32).map(
+            |c| c.to_string(),
+        )
+    } else if let Some(number) = name.strip_prefix('#') {
+        number.parse::<u32>().ok().and_then(::std::char::from_u32).map(|c| c.to_string())
+    } else {
+        ENTITIES
+            .iter()
+            .find(|&&(entity, _)| entity == name)
+            .map(|&(_, replacement)| replacement.to_string())
+    };
+    replacement.map(|r| (r, end + 1))
+}
+
+/// Find end of tag starting text, after its `>`, quoted attribute values can contain `>`
+fn tag_end(text: &str) -> usize {
+    let mut quote = None;
+    for (idx, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return idx + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// Lowercase name of tag starting text, like `p` for `</P>`
+fn tag_name(tag: &str) -> String {
+    tag.trim_start_matches('<')
+        .trim_start_matches('/')
+        .chars()
+        .take_while(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Convert html to plain text, to check content of html mails
+///
+/// Tags are removed, blocks and line breaks start new lines, whitespace is collapsed except in
+/// `pre` and hidden content like scripts and styles is dropped.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    let mut pre = 0;
+    let mut space = false;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("<!--") {
+            rest = match rest.find("-->") {
+                Some(idx) => &rest[idx + 3..],
+                None => "",
+            };
+            continue;
+        }
+        let is_tag = c == '<' &&
+            rest[1..]
+                .chars()
+                .next()
+                .map(|c| c.is_alphabetic() || c == '/' || c == '!' || c == '?') ==
+                Some(true);
+        if is_tag {
+            let end = tag_end(rest);
+            let (tag, after) = rest.split_at(end);
+            rest = after;
+            let name = tag_name(tag);
+            let closing = tag.starts_with("</");
+            if !closing && HIDDEN_TAGS.contains(&name.as_str()) {
+                // Skip content until closing tag
+                let lower = rest.to_ascii_lowercase();
+                rest = match lower.find(&format!("</{}", name)) {
+                    Some(idx) => &rest[idx + tag_end(&rest[idx..])..],
+                    None => "",
+                };
+            } else if name == "br" {
+                text.push('\n');
+                space = false;
+            } else if name == "pre" || BLOCK_TAGS.contains(&name.as_str()) {
+                if name == "pre" && closing && pre > 0 {
+                    pre -= 1;
+                } else if name == "pre" && !closing {
+                    pre += 1;
+                }
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                space = false;
+            } else if name == "td" || name == "th" {
+                space = true;
+            }
+            continue;
+        }
+        let (chunk, len) = if c == '&' {
+            match entity(rest) {
+                Some((replacement, len)) => (replacement, len),
+                None => (c.to_string(), c.len_utf8()),
+            }
+        } else {
+            (c.to_string(), c.len_utf8())
+        };
+        rest = &rest[len..];
+        for c in chunk.chars() {
+            if pre > 0 {
+                text.push(c);
+            } else if c.is_whitespace() {
+                space = true;
+            } else {
+                if space && !text.is_empty() && !text.ends_with('\n') {
+                    text.push(' ');
+                }
+                space = false;
+                text.push(c);
+            }
+        }
+    }
+    // Trim line ends and keep at most one empty line between paragraphs
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(|l| l.trim_end()) {
+        if !line.is_empty() || lines.last().map(|l| !l.is_empty()) == Some(true) {
+            lines.push(line);
+        }
+    }
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn html_text() {
+        let html = "<html><head><title>Hi</title><style>p { color: red; }</style></head>\
+                    <body><p>Your  <b>invoice</b>\r\n is ready.</p><!-- <p>hidden</p> -->\
+                    <div>Total: 5&nbsp;&euro; &amp; more<br>Thanks&#33;</div>\
+                    <script type=\"text/javascript\">if (a < b) {}</script>\
+                    <a href=\"x?a>b\">Link</a> &unknown; 1 < 2</body></html>";
+        assert_eq!(
+            html_to_text(html),
+            "Your invoice is ready.\nTotal: 5 € & more\nThanks!\nLink &unknown; 1 < 2"
+        );
+    }
+
+    #[test]
+    fn html_text_pre_and_tables() {
+        let html = "<P>Before</P><pre>a  b\n  c</pre><table><tr><td>1</td><td>2</td></tr>\
+                    <TR><TD>3</TD><td>&#x263A;</td></tr></table>";
+        assert_eq!(html_to_text(html), "Before\na  b\n  c\n1 2\n3 ☺");
+    }
+
+    #[test]
+    fn html_text_paragraphs() {
+        assert_eq!(html_to_text("<p>a</p>\n\n<p></p><p></p><p>b</p>"), "a\nb");
+        assert_eq!(html_to_text("a<br><br><br>b"), "a\n\nb");
+        assert_eq!(html_to_text(""), "");
+    }
+}
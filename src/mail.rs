@@ -1,5 +1,9 @@
+use address::parse_address_list;
+use chrono::{DateTime, FixedOffset};
+use decode::{decode_body, decode_words};
 use error::*;
-use mailparse::*;
+use mime::Part;
+use threading::Thread;
 
 /// Structure representing a mail address
 #[derive(Clone, Debug, PartialEq)]
@@ -12,21 +16,16 @@ impl MailAddress {
     /// Create a new mail address
     pub fn new(name: String, address: String) -> MailAddress {
         MailAddress {
-            name: name,
-            address: address,
+            name,
+            address,
         }
     }
-    /// Parse address from a value obtained by imap
+
+    /// Parse first address of an address list, address is empty when there is none
     pub fn parse_imap<S: AsRef<str>>(value: S) -> MailAddress {
-        let mut value = value.as_ref().to_string();
-        let idx = value.find('<').unwrap_or(value.len());
-        let name = value.drain(..idx).collect::<String>();
-        if value.len() > 0 {
-            let idx = value.find('>').unwrap_or(value.len());
-            let address = value.drain(1..idx).collect();
-            MailAddress::new(name.trim().to_string(), address)
-        } else {
-            MailAddress::new(String::new(), name.trim().to_string())
+        match parse_address_list(value.as_ref()).into_iter().next() {
+            Some(address) => address,
+            None => MailAddress::new(String::new(), String::new()),
         }
     }
 }
@@ -34,51 +33,295 @@ impl MailAddress {
 /// Structure representing a mail
 #[derive(Debug)]
 pub struct Mail {
+    pub uid: u32,
     pub from: Vec<MailAddress>,
     pub to: Vec<MailAddress>,
     pub cc: Vec<MailAddress>,
     pub subject: String,
     pub content: String,
+    /// Headers with their unfolded raw value, encoded words aren't decoded
+    pub headers: Vec<(String, String)>,
+    /// Date given by sender in Date header
+    pub date: Option<DateTime<FixedOffset>>,
+    /// Date when server received the mail, its imap INTERNALDATE
+    pub received: Option<DateTime<FixedOffset>>,
+    /// Size of whole mail in bytes, its imap RFC822.SIZE
+    pub size: Option<u64>,
+    /// Mime structure, its imap BODYSTRUCTURE
+    pub structure: Option<Part>,
+    /// Flags and keywords, its imap FLAGS, kept up to date by actions
+    pub flags: Vec<String>,
+    /// Message-ID header, without angle brackets
+    pub message_id: Option<String>,
+    /// Ids of mails this mail replies to, from In-Reply-To header
+    pub in_reply_to: Vec<String>,
+    /// Ids of previous mails of thread, first one is usually the thread start
+    pub references: Vec<String>,
+    /// Key of thread in state, see `threading::identify`
+    pub thread_id: Option<String>,
+    /// Thread as remembered in state, empty if rules never acted on it
+    pub thread: Thread,
+    /// List-Id header, without angle brackets nor description (RFC 2919)
+    pub list_id: Option<String>,
+    /// Address to post to the list, from List-Post header, like `mailto:list@example.com`
+    pub list_post: Option<String>,
+    /// Addresses to unsubscribe from the list, from List-Unsubscribe header (RFC 2369)
+    pub list_unsubscribe: Vec<String>,
+    /// Lowercase Precedence header, like `list` or `bulk`
+    pub precedence: Option<String>,
+}
+
+/// Parse message ids of headers like References, like `<a@example.com> <b@example.com>`
+///
+/// Angle brackets are removed, comments and text outside brackets are ignored. Addresses of list
+/// headers like List-Unsubscribe are written the same way.
+pub fn parse_message_ids(value: &str) -> Vec<String> {
+    let mut ids = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('<') {
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let id: String = rest[start + 1..end].split_whitespace().collect();
+        if !id.is_empty() {
+            ids.push(id);
+        }
+        rest = &rest[end + 1..];
+    }
+    ids
+}
+
+/// Parse List-Id header, like `Rust users <rust-users.lists.rust-lang.org>`
+///
+/// Old lists may give the id without angle brackets.
+fn parse_list_id(value: &str) -> Option<String> {
+    match parse_message_ids(value).into_iter().next() {
+        Some(id) => Some(id),
+        None if !value.is_empty() && !value.contains(char::is_whitespace) => {
+            Some(value.to_string())
+        }
+        None => None,
+    }
+}
+
+/// System flags of RFC 3501, with their usual case
+const SYSTEM_FLAGS: [&str; 6] = [
+    "\\Seen",
+    "\\Answered",
+    "\\Flagged",
+    "\\Deleted",
+    "\\Draft",
+    "\\Recent",
+];
+
+/// Give system flags their usual case, like `\seen` to `\Seen`, keywords are kept
+pub fn normalize_flag(flag: &str) -> String {
+    match SYSTEM_FLAGS.iter().find(|f| f.eq_ignore_ascii_case(flag)) {
+        Some(flag) => flag.to_string(),
+        None => flag.to_string(),
+    }
+}
+
+/// Parse date of Date header (RFC 5322), comments like `(CEST)` are ignored
+pub fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    let value = match value.find('(') {
+        Some(idx) => &value[..idx],
+        None => value,
+    };
+    DateTime::parse_from_rfc2822(value.trim()).ok()
+}
+
+/// Read headers at start of mail, with their unfolded raw value
+fn parse_headers(text: &str) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in text.lines() {
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(&mut (_, ref mut value)) = headers.last_mut() {
+                value.push_str(line);
+            }
+            continue;
+        }
+        if let Some(idx) = line.find(':') {
+            headers.push((line[..idx].trim().to_string(), line[idx + 1..].trim().to_string()));
+        }
+    }
+    headers
+}
+
+/// Text after headers of mail
+fn body(text: &str) -> &str {
+    match (text.find("\r\n\r\n"), text.find("\n\n")) {
+        (Some(crlf), Some(lf)) if lf < crlf => &text[lf + 2..],
+        (Some(crlf), _) => &text[crlf + 4..],
+        (None, Some(lf)) => &text[lf + 2..],
+        (None, None) => "",
+    }
+}
+
+/// Get parameter of a header value, like charset of `text/plain; charset="utf-8"`
+fn header_param(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let mut parts = param.splitn(2, '=');
+        let key = parts.next().unwrap_or_default().trim();
+        match parts.next() {
+            Some(param_value) if key.eq_ignore_ascii_case(name) => {
+                Some(param_value.trim().trim_matches('"').to_string())
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Parse imap INTERNALDATE, like `17-Jul-1996 02:44:25 -0700`
+pub fn parse_internal_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_str(value.trim(), "%d-%b-%Y %H:%M:%S %z").ok()
 }
 
 impl Mail {
     /// Parse mail from fetch result
+    ///
+    /// Subject, display names and text are decoded and normalized, other headers keep their raw
+    /// value.
     pub fn parse_fetched(fetched: Vec<String>) -> Result<Mail> {
-        let fetched = fetched.into_iter().map(|s| s).collect::<String>();
-        let parsed = parse_mail(fetched.as_bytes())?;
-        let from = parsed
-            .headers
-            .get_all_values("From")?
-            .join(", ")
-            .split(", ")
-            .map(|s| MailAddress::parse_imap(s))
-            .collect();
-        let to = parsed
-            .headers
-            .get_all_values("To")?
-            .join(", ")
-            .split(", ")
-            .map(|s| MailAddress::parse_imap(s))
-            .collect();
-        let cc = parsed
-            .headers
-            .get_all_values("Cc")?
-            .join(", ")
-            .split(", ")
-            .map(|s| MailAddress::parse_imap(s))
-            .collect();
-        let subject = parsed
-            .headers
-            .get_first_value("Subject")?
+        let fetched = fetched.into_iter().collect::<String>();
+        let mut mail = Mail {
+            uid: 0,
+            from: Vec::new(),
+            to: Vec::new(),
+            cc: Vec::new(),
+            subject: String::new(),
+            content: String::new(),
+            headers: parse_headers(&fetched),
+            date: None,
+            received: None,
+            size: None,
+            structure: None,
+            flags: Vec::new(),
+            message_id: None,
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            thread_id: None,
+            thread: Thread::default(),
+            list_id: None,
+            list_post: None,
+            list_unsubscribe: Vec::new(),
+            precedence: None,
+        };
+        let charset = mail.header("Content-Type").and_then(|t| header_param(t, "charset"));
+        let transfer_encoding = mail.header("Content-Transfer-Encoding").unwrap_or_default();
+        mail.content = decode_body(
+            body(&fetched).as_bytes(),
+            transfer_encoding,
+            &charset.unwrap_or_default(),
+        );
+        mail.from = parse_address_list(&mail.header_values("From").join(", "));
+        mail.to = parse_address_list(&mail.header_values("To").join(", "));
+        mail.cc = parse_address_list(&mail.header_values("Cc").join(", "));
+        mail.subject = decode_words(mail.header("Subject").unwrap_or_default());
+        mail.date = mail.header("Date").and_then(parse_date);
+        mail.message_id = mail.header("Message-ID")
+            .map(parse_message_ids)
+            .and_then(|ids| ids.into_iter().next());
+        mail.in_reply_to = mail.header("In-Reply-To").map(parse_message_ids).unwrap_or_default();
+        mail.references = mail.header("References").map(parse_message_ids).unwrap_or_default();
+        mail.list_id = mail.header("List-Id").and_then(parse_list_id);
+        // `List-Post: NO` means that posting isn't allowed, it has no address
+        mail.list_post = mail.header("List-Post")
+            .map(parse_message_ids)
+            .and_then(|uris| uris.into_iter().next());
+        mail.list_unsubscribe = mail.header("List-Unsubscribe")
+            .map(parse_message_ids)
             .unwrap_or_default();
-        let content = parsed.get_body()?;
-        Ok(Mail {
-            from: from,
-            to: to,
-            cc: cc,
-            subject: subject,
-            content: content,
-        })
+        mail.precedence = mail.header("Precedence").map(|p| p.to_lowercase());
+        Ok(mail)
+    }
+
+    /// Get first value of header, name is case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    }
+
+    /// Get every value of header, name is case insensitive
+    pub fn header_values(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|&(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+            .collect()
+    }
+
+    /// Message ids related to thread of mail: its references, its parents and its own id
+    pub fn thread_ids(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = Vec::new();
+        let all = self.references
+            .iter()
+            .chain(self.in_reply_to.iter())
+            .chain(self.message_id.iter());
+        for id in all {
+            if !ids.contains(&id.as_str()) {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    /// Check if mail was sent through a mailing list
+    pub fn is_mailing_list(&self) -> bool {
+        self.list_id.is_some() || self.list_post.is_some() ||
+            self.precedence.as_ref().map(|p| p == "list") == Some(true)
+    }
+
+    /// Name of mailing list, usable as folder name, like `rust-users-lists-rust-lang-org`
+    ///
+    /// It is the whole List-Id, or the List-Post address, so lists of different hosts sharing a
+    /// name don't share a folder. Characters other than letters, digits and `_`, like hierarchy
+    /// delimiters, are replaced by `-`.
+    pub fn list_name(&self) -> Option<String> {
+        let name = match (self.list_id.as_ref(), self.list_post.as_ref()) {
+            (Some(id), _) => id.to_string(),
+            (None, Some(post)) if post.to_lowercase().starts_with("mailto:") => {
+                post[7..].split('?').next().unwrap_or_default().to_string()
+            }
+            _ => return None,
+        };
+        let name: String = name.to_lowercase()
+            .chars()
+            .map(|c| if c.is_alphanumeric() || c == '_' { c } else { '-' })
+            .collect();
+        let name = name.trim_matches('-');
+        if name.is_empty() {
+            None
+        } else {
+            Some(name.to_string())
+        }
+    }
+
+    /// Check if mail has flag or keyword, case insensitive
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f.eq_ignore_ascii_case(flag))
+    }
+
+    /// Add flags given as in imap, like `\Seen $Label1`
+    pub fn add_flags(&mut self, flags: &str) {
+        for flag in flags.split_whitespace() {
+            if !self.has_flag(flag) {
+                self.flags.push(normalize_flag(flag));
+            }
+        }
+    }
+
+    /// Remove flags given as in imap, like `\Seen $Label1`
+    pub fn remove_flags(&mut self, flags: &str) {
+        for flag in flags.split_whitespace() {
+            self.flags.retain(|f| !f.eq_ignore_ascii_case(flag));
+        }
     }
 }
 
@@ -95,4 +338,156 @@ mod unit_tests {
             "error with parsing"
         );
     }
+
+    #[test]
+    fn mail_addresses() {
+        let mail = Mail::parse_fetched(vec![
+            "From: \"Doe, John\" <jd@x.com>\r\n".to_string(),
+            "To: undisclosed-recipients:;\r\n".to_string(),
+            "Cc: \r\n\r\n".to_string(),
+        ]).unwrap();
+        assert_eq!(
+            mail.from,
+            vec![MailAddress::new("Doe, John".to_string(), "jd@x.com".to_string())]
+        );
+        assert!(mail.to.is_empty());
+        assert!(mail.cc.is_empty());
+        assert_eq!(MailAddress::parse_imap("").address, "");
+    }
+
+    #[test]
+    fn mail_decoding() {
+        let mail = Mail::parse_fetched(vec![
+            "Subject: =?ISO-8859-1?Q?Ihre_Rechnung_f=FCr_M=E4rz?=\r\n".to_string(),
+            "From: =?Shift_JIS?B?g2WDWINn?= <t@x.jp>\r\n".to_string(),
+            "To: =?UTF-8?Q?a?=\r\n =?UTF-8?Q?b?= <ab@x.com>\r\n".to_string(),
+            "Content-Type: text/plain; charset=utf-8\r\n\r\n".to_string(),
+            "Cafe\u{301}".to_string(),
+        ]).unwrap();
+        assert_eq!(mail.subject, "Ihre Rechnung für März");
+        assert_eq!(mail.from[0].name, "テスト");
+        assert_eq!(mail.to[0].name, "ab");
+        assert_eq!(mail.content, "Café");
+        assert_eq!(mail.header("Subject"), Some("=?ISO-8859-1?Q?Ihre_Rechnung_f=FCr_M=E4rz?="));
+    }
+
+    #[test]
+    fn mail_body_charset() {
+        let mail = Mail::parse_fetched(vec![
+            "Content-Type: text/plain; charset=\"ISO-8859-1\"\r\n".to_string(),
+            "Content-Transfer-Encoding: quoted-printable\r\n\r\n".to_string(),
+            "Gr=FC=DFe".to_string(),
+        ]).unwrap();
+        assert_eq!(mail.content, "Grüße");
+        let mail = Mail::parse_fetched(vec!["Subject: Hehe\n\nline 1\n\nline 2".to_string()])
+            .unwrap();
+        assert_eq!(mail.content, "line 1\n\nline 2");
+        assert_eq!(
+            header_param("text/plain; format=flowed; Charset=utf-8", "charset"),
+            Some("utf-8".to_string())
+        );
+    }
+
+    #[test]
+    fn mail_threading_headers() {
+        let mail = Mail::parse_fetched(vec![
+            "Message-ID: <c@x.com>\r\n".to_string(),
+            "In-Reply-To: <b@x.com> (Bob's message)\r\n".to_string(),
+            "References: <a@x.com>\r\n <b@x.com>\r\n\r\n".to_string(),
+        ]).unwrap();
+        assert_eq!(mail.message_id, Some("c@x.com".to_string()));
+        assert_eq!(mail.in_reply_to, vec!["b@x.com"]);
+        assert_eq!(mail.references, vec!["a@x.com", "b@x.com"]);
+        assert_eq!(mail.thread_ids(), vec!["a@x.com", "b@x.com", "c@x.com"]);
+        assert_eq!(parse_message_ids("<a@x.com><b @x.com> junk <"), vec!["a@x.com", "b@x.com"]);
+        assert!(parse_message_ids("none").is_empty());
+    }
+
+    #[test]
+    fn mail_list_headers() {
+        let mail = Mail::parse_fetched(vec![
+            "List-Id: Rust users <rust-users.lists.rust-lang.org>\r\n".to_string(),
+            "List-Post: <mailto:rust-users@lists.rust-lang.org>\r\n".to_string(),
+            "List-Unsubscribe: <mailto:leave@lists.rust-lang.org?subject=bye>,\r\n".to_string(),
+            " <https://lists.rust-lang.org/unsubscribe>\r\n".to_string(),
+            "Precedence: List\r\n\r\n".to_string(),
+        ]).unwrap();
+        assert_eq!(mail.list_id, Some("rust-users.lists.rust-lang.org".to_string()));
+        assert_eq!(mail.list_post, Some("mailto:rust-users@lists.rust-lang.org".to_string()));
+        assert_eq!(
+            mail.list_unsubscribe,
+            vec![
+                "mailto:leave@lists.rust-lang.org?subject=bye",
+                "https://lists.rust-lang.org/unsubscribe",
+            ]
+        );
+        assert_eq!(mail.precedence, Some("list".to_string()));
+        assert!(mail.is_mailing_list());
+        assert_eq!(mail.list_name(), Some("rust-users-lists-rust-lang-org".to_string()));
+
+        let mail = Mail::parse_fetched(vec![
+            "List-Id: old.list.example.com\r\n".to_string(),
+            "List-Post: NO\r\n\r\n".to_string(),
+        ]).unwrap();
+        assert_eq!(mail.list_post, None);
+        assert_eq!(mail.list_name(), Some("old-list-example-com".to_string()));
+
+        let mail = Mail::parse_fetched(vec![
+            "List-Post: <mailto:Dev+Team@x.org?subject=hi>\r\n\r\n".to_string(),
+        ]).unwrap();
+        assert!(mail.is_mailing_list());
+        assert_eq!(mail.list_name(), Some("dev-team-x-org".to_string()));
+
+        // Lists sharing a name on different hosts get their own folder
+        let list = |id: &str| {
+            Mail::parse_fetched(vec![format!("List-Id: <{}>\r\n\r\n", id)]).unwrap().list_name()
+        };
+        assert_ne!(list("users.rust-lang.org"), list("users.python.org"));
+        assert_eq!(list("users.python.org"), Some("users-python-org".to_string()));
+
+        let mail = Mail::parse_fetched(vec!["Precedence: bulk\r\n\r\n".to_string()]).unwrap();
+        assert!(!mail.is_mailing_list());
+        assert_eq!(mail.list_name(), None);
+    }
+
+    #[test]
+    fn mail_header() {
+        let mail = Mail::parse_fetched(vec![
+            "Subject: Hehe\r\n".to_string(),
+            "precedence: bulk\r\n\r\n".to_string(),
+        ]).unwrap();
+        assert_eq!(mail.header("Precedence"), Some("bulk"));
+        assert_eq!(mail.header("SUBJECT"), Some("Hehe"));
+        assert_eq!(mail.header("List-Id"), None);
+    }
+
+    #[test]
+    fn mail_dates() {
+        let date = DateTime::parse_from_rfc3339("2003-07-01T10:52:37+02:00").unwrap();
+        assert_eq!(parse_date("Tue, 1 Jul 2003 10:52:37 +0200"), Some(date));
+        assert_eq!(parse_date("Tue, 1 Jul 2003 10:52:37 +0200 (CEST)"), Some(date));
+        assert_eq!(parse_date("yesterday"), None);
+        assert_eq!(parse_internal_date(" 1-Jul-2003 10:52:37 +0200"), Some(date));
+        assert_eq!(parse_internal_date("01-Jul-2003 10:52:37 +0200"), Some(date));
+        let mail = Mail::parse_fetched(vec![
+            "Subject: Hehe\r\nDate: Tue, 1 Jul 2003 10:52:37 +0200\r\n\r\n".to_string(),
+        ]).unwrap();
+        assert_eq!(mail.date, Some(date));
+        assert_eq!(mail.received, None);
+    }
+
+    #[test]
+    fn mail_flags() {
+        let mut mail = Mail::parse_fetched(vec!["Subject: Hehe\r\n\r\n".to_string()]).unwrap();
+        assert_eq!(normalize_flag("\\SEEN"), "\\Seen");
+        assert_eq!(normalize_flag("$Label1"), "$Label1");
+        mail.add_flags("\\seen $Label1");
+        assert_eq!(mail.flags, vec!["\\Seen", "$Label1"]);
+        mail.add_flags("\\Seen \\Flagged");
+        assert_eq!(mail.flags, vec!["\\Seen", "$Label1", "\\Flagged"]);
+        assert!(mail.has_flag("$label1"));
+        mail.remove_flags("\\SEEN $Label2");
+        assert_eq!(mail.flags, vec!["$Label1", "\\Flagged"]);
+        assert!(!mail.has_flag("\\Seen"));
+    }
 }
@@ -1,105 +1,400 @@
+// Tests are kept in the style they were written in
+#![cfg_attr(test, allow(deprecated))]
+#![cfg_attr(
+    test,
+    allow(
+        clippy::manual_pattern_char_comparison,
+        clippy::needless_borrows_for_generic_args,
+        clippy::option_as_ref_deref,
+        clippy::redundant_field_names,
+        clippy::redundant_static_lifetimes,
+        clippy::useless_vec
+    )
+)]
+
+extern crate base64;
+extern crate caseless;
+extern crate chrono;
+extern crate chrono_tz;
 #[macro_use]
 extern crate clap;
+extern crate encoding;
 #[macro_use]
 extern crate error_chain;
+extern crate glob;
 extern crate imap;
-extern crate mailparse;
 extern crate openssl;
+extern crate rand;
+extern crate regex;
 #[macro_use]
 extern crate serde_derive;
 extern crate toml;
+extern crate unicode_normalization;
 extern crate unix_daemonize;
 
 mod account;
+mod address;
+mod backoff;
+mod compose;
 mod connection;
 mod config;
+mod decode;
 mod error;
+mod folder;
+mod html;
 mod mail;
+mod mime;
+mod response;
 mod rule;
+mod smtp;
+mod state;
+mod threading;
+mod timezone;
 
+use backoff::Backoff;
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use clap::{App, Arg};
 use connection::Connection;
 use config::Config;
 use error::*;
 use mail::Mail;
+use response::ListEntry;
+use rule::{Action, Context, Rule};
+use state::State;
+use std::collections::BTreeSet;
 use std::path::Path;
 use std::thread;
 use unix_daemonize::{daemonize_redirect, ChdirMode};
 
-fn apply_rules(
-    mail: &Mail,
+/// Maximum number of mails fetched with one command
+const FETCH_BATCH_SIZE: usize = 100;
+
+/// Parent of folders where mailing lists are filed, see `file_lists` in account
+const LISTS_FOLDER: &str = "Lists";
+
+/// Seconds between two checks of rules on age on mails already processed
+const RESCAN_INTERVAL: i64 = 3600;
+
+/// Longest wait with IDLE in seconds, servers may close connections idle for 30 minutes
+const IDLE_TIMEOUT: u64 = 29 * 60;
+
+/// Apply actions of a rule matching mail, true is returned if following rules must be skipped
+fn apply_actions(
+    rule: &Rule,
+    mail: &mut Mail,
+    mailbox: &ListEntry,
     connection: &mut Connection,
     config: &Config,
-    i: usize,
+    state: &mut State,
 ) -> Result<bool> {
-    'rule_loop: for rule in &config.rules {
-        let mut condition_check = true;
-        for condition in &rule.conditions {
-            condition_check = condition.check(&mail);
-            if !condition_check && !rule.any {
-                continue 'rule_loop;
-            } else if !condition_check {
+    println!("[{}] mail meet conditions: {}", rule.name, mail.subject);
+    for action in &rule.actions {
+        action.apply(connection, config, state, mailbox, mail)?;
+        if action.is_rules_stop() || action.is_remove() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn apply_rules(
+    mail: &mut Mail,
+    mailbox: &ListEntry,
+    connection: &mut Connection,
+    config: &Config,
+    state: &mut State,
+) -> Result<()> {
+    let context = Context::new(config.timezone);
+    threading::load(mail, state);
+    for rule in &config.rules {
+        if !rule.applies_to(mailbox) || !rule.matches(mail, &context) {
+            continue;
+        }
+        if apply_actions(rule, mail, mailbox, connection, config, state)? {
+            return Ok(());
+        }
+    }
+    // Mails still here after rules are filed with their list, mails already filed are kept
+    let delimiter = mailbox.delimiter.as_deref().unwrap_or("/");
+    let lists_prefix = format!("{}{}", LISTS_FOLDER, delimiter);
+    if config.account.file_lists == Some(true) && !mailbox.name.starts_with(&lists_prefix) {
+        if let Some(name) = mail.list_name() {
+            let folder = format!("{}{}", lists_prefix, name);
+            println!("[lists] filing mail into {}: {}", folder, mail.subject);
+            Action::move_to(folder).apply(connection, config, state, mailbox, mail)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check that every special use in rules, like `move to \Junk`, has a mailbox
+fn check_special_folders(connection: &Connection, config: &Config) -> Result<()> {
+    for rule in &config.rules {
+        for action in &rule.actions {
+            if let Some(special_use) = action.special_use() {
+                if connection.special_folder(special_use).is_none() {
+                    bail!(ErrorKind::MissingRuleFolder(
+                        rule.name.clone(),
+                        special_use.attribute().to_string(),
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Apply rules to every mail not processed yet
+///
+/// When a mailbox is first seen, mails already there are skipped, forwarding or answering them
+/// would be a surprise. When its uid validity changed, known uids are meaningless: mails received
+/// since the last check are searched by date instead. State is saved after each mail so a restart
+/// never process a mail twice. A mail whose rules fail is skipped, only a lost connection stops
+/// processing. Rules on age are then checked again on processed mails, see `rescan_mailbox`.
+fn process_mailbox(
+    connection: &mut Connection,
+    config: &Config,
+    state: &mut State,
+    mailbox_entry: &ListEntry,
+) -> Result<()> {
+    let mailbox = mailbox_entry.name.as_str();
+    connection.select(mailbox)?;
+    let (uid_validity, uid_next) = connection.uid_status(mailbox)?;
+    let checked = Utc::now().timestamp();
+    let mut reset_since = None;
+    if state.mailbox(mailbox).uid_validity != uid_validity {
+        let last_check = state.mailbox(mailbox).last_check;
+        if last_check > 0 {
+            println!(
+                "uids of {} were reset, mails received since last check are processed",
+                mailbox
+            );
+            reset_since = Some(last_check);
+        } else {
+            println!("{} is new, only mails received from now on are processed", mailbox);
+        }
+        state.mailbox(mailbox).uid_validity = uid_validity;
+        state.mailbox(mailbox).last_uid = uid_next.saturating_sub(1);
+        state.save()?;
+    }
+    let last_uid = state.mailbox(mailbox).last_uid;
+    let mut uids = Vec::new();
+    if let (Some(since), true) = (reset_since, last_uid > 0) {
+        // Search dates ignore time, mails received earlier that day are skipped once fetched
+        let since = Utc.timestamp_opt(since, 0).single().unwrap_or_else(Utc::now);
+        let criteria = format!("UID 1:{} SINCE {}", last_uid, search_date(since));
+        uids.extend(connection.uid_search(&criteria)?);
+    }
+    uids.extend(connection.uids_after(last_uid)?);
+    if !uids.is_empty() {
+        println!("{} new mail(s) in {}", uids.len(), mailbox);
+    }
+    for batch in uids.chunks(FETCH_BATCH_SIZE) {
+        for mut mail in fetch_mails(connection, config, state, batch)? {
+            let checked_before = match (reset_since, mail.received) {
+                (Some(since), Some(received)) => received.timestamp() < since,
+                _ => false,
+            };
+            if checked_before {
                 continue;
             }
-            if rule.any {
-                break;
+            if let Err(e) = apply_rules(&mut mail, mailbox_entry, connection, config, state) {
+                if e.is_connection() {
+                    return Err(e);
+                }
+                println!("[{}] mail skipped, rules failed: {}: {}", mailbox, mail.subject, e);
             }
+            // Mails searched after a reset are below last uid, it never goes back
+            let last_uid = state.mailbox(mailbox).last_uid.max(mail.uid);
+            state.mailbox(mailbox).last_uid = last_uid;
+            state.save()?;
+        }
+        connection.expunge_deleted()?;
+        // Mails deleted since uids were listed are skipped too
+        let last_uid = state.mailbox(mailbox).last_uid.max(batch[batch.len() - 1]);
+        state.mailbox(mailbox).last_uid = last_uid;
+        state.save()?;
+    }
+    state.mailbox(mailbox).last_check = checked;
+    state.save()?;
+    rescan_mailbox(connection, config, state, mailbox_entry)
+}
+
+/// Fetch mails of selected mailbox and find their threads
+fn fetch_mails(
+    connection: &mut Connection,
+    config: &Config,
+    state: &mut State,
+    uids: &[u32],
+) -> Result<Vec<Mail>> {
+    let mut mails = connection.fetch_mails(uids, &config.headers())?;
+    // Server threads only cover this batch, like local ones, but they also use subjects
+    // (RFC 5256). Mails of previous batches are linked through state.
+    let threads = match connection.thread_uids(uids)? {
+        Some(uid_threads) => threading::uid_threads(&mails, &uid_threads),
+        None => threading::thread_mails(&mails),
+    };
+    threading::identify(&mut mails, &threads, state);
+    Ok(mails)
+}
+
+/// Format date for search commands, like `1-Feb-2026`
+fn search_date(date: DateTime<Utc>) -> String {
+    date.format("%-d-%b-%Y").to_string()
+}
+
+/// Apply rules on age matching mail now but not at previous check
+#[allow(clippy::too_many_arguments)]
+fn apply_age_rules(
+    mail: &mut Mail,
+    mailbox: &ListEntry,
+    rules: &[&Rule],
+    previous: &Context,
+    context: &Context,
+    connection: &mut Connection,
+    config: &Config,
+    state: &mut State,
+) -> Result<()> {
+    threading::load(mail, state);
+    for rule in rules {
+        if !rule.starts_matching(mail, previous, context) {
+            continue;
         }
-        if !condition_check && rule.any {
-            continue 'rule_loop;
+        if apply_actions(rule, mail, mailbox, connection, config, state)? {
+            return Ok(());
         }
-        for exception in &rule.exceptions {
-            if exception.check(&mail) {
-                continue 'rule_loop;
-            }
+    }
+    Ok(())
+}
+
+/// Check rules on age, like `age > 30d`, again on mails already processed
+///
+/// Such a rule can start matching a mail long after it was received. Every hour, mails whose age
+/// crossed a limit of a rule since previous check are fetched again, with their current flags,
+/// and the rule is applied if it matches now but didn't at previous check. This way a rule acts
+/// only once on a mail. Mails are checked from the first check on: mails too old at that time
+/// are left alone.
+fn rescan_mailbox(
+    connection: &mut Connection,
+    config: &Config,
+    state: &mut State,
+    mailbox_entry: &ListEntry,
+) -> Result<()> {
+    let mailbox = mailbox_entry.name.as_str();
+    let now = Utc::now();
+    let last_rescan = state.mailbox(mailbox).last_rescan;
+    if now.timestamp() - last_rescan < RESCAN_INTERVAL {
+        return Ok(());
+    }
+    let last_uid = state.mailbox(mailbox).last_uid;
+    let rules = config
+        .rules
+        .iter()
+        .filter(|r| r.applies_to(mailbox_entry) && !r.ages().is_empty())
+        .collect::<Vec<&Rule>>();
+    if last_rescan > 0 && last_uid > 0 && !rules.is_empty() {
+        let previous = Utc.timestamp_opt(last_rescan, 0).single().unwrap_or(now);
+        let ages = rules.iter().flat_map(|r| r.ages()).collect::<BTreeSet<Duration>>();
+        let mut uids = BTreeSet::new();
+        for age in ages {
+            // Search dates ignore time and timezone, a day is added on both sides
+            let criteria = format!(
+                "UID 1:{} SINCE {} BEFORE {}",
+                last_uid,
+                search_date(previous - age - Duration::days(1)),
+                search_date(now - age + Duration::days(2))
+            );
+            uids.extend(connection.uid_search(&criteria)?);
         }
-        println!("[{}] mail meet conditions: {}", rule.name, mail.subject);
-        for action in &rule.actions {
-            action.apply(connection, i)?;
-            if action.is_rules_stop() {
-                return Ok(false);
-            }
-            if action.is_remove() {
-                return Ok(true);
+        let uids = uids.into_iter().collect::<Vec<u32>>();
+        let previous = Context::at(previous, config.timezone);
+        let context = Context::at(now, config.timezone);
+        for batch in uids.chunks(FETCH_BATCH_SIZE) {
+            for mut mail in fetch_mails(connection, config, state, batch)? {
+                let result = apply_age_rules(
+                    &mut mail,
+                    mailbox_entry,
+                    &rules,
+                    &previous,
+                    &context,
+                    connection,
+                    config,
+                    state,
+                );
+                if let Err(e) = result {
+                    if e.is_connection() {
+                        return Err(e);
+                    }
+                    println!("[{}] mail skipped, rules failed: {}: {}", mailbox, mail.subject, e);
+                }
             }
+            connection.expunge_deleted()?;
         }
     }
-    Ok(false)
+    state.mailbox(mailbox).last_rescan = now.timestamp();
+    state.save()
 }
 
-fn manage_account<P: AsRef<Path>>(path: P) -> Result<()> {
-    let config = Config::from_file(path)?;
+/// Connect to account and apply rules to new mails until connection fails
+///
+/// Backoff is reset once every watched mailbox was synced, a server closing the connection
+/// during sync keeps increasing the delay.
+fn run_session(config: &Config, state: &mut State, backoff: &mut Backoff) -> Result<()> {
     let mut connection = Connection::connect(&config.account)?;
     connection.set_debug(false);
-    connection.select("INBOX")?;
-    let mut i = 0;
-    let mut len = connection.mail_number("INBOX")?;
-    while i < len {
-        i += 1;
-        let mail = connection.fetch_mail(i)?;
-        if apply_rules(&mail, &mut connection, &config, i)? {
-            i -= 1;
-            len -= 1;
-        }
-    }
+    connection.discover_folders(&config.folders)?;
+    check_special_folders(&connection, config)?;
     let sync = config.account.sync.unwrap_or(60);
+    let patterns = config.account.mailboxes();
+    let mut watched = 0;
     loop {
-        for _ in 0..(sync / 5) {
+        // Mailboxes are listed each time to notice new mailboxes matching patterns
+        let mailboxes = connection.watched_mailboxes(&patterns)?;
+        if mailboxes.len() > 1 && mailboxes.len() != watched {
+            println!(
+                "watching {} mailboxes, IDLE only works with one: checking them every {}s",
+                mailboxes.len(),
+                sync
+            );
+        }
+        watched = mailboxes.len();
+        for mailbox in &mailboxes {
+            process_mailbox(&mut connection, config, state, mailbox)?;
+        }
+        state.prune_threads(Utc::now().timestamp());
+        backoff.reset();
+        // Only the selected mailbox can be watched with IDLE
+        if mailboxes.len() == 1 && connection.has_capability("IDLE") {
+            connection.idle(::std::time::Duration::from_secs(IDLE_TIMEOUT))?;
+        } else {
+            for _ in 0..(sync / 5) {
+                connection.noop()?;
+                thread::sleep(::std::time::Duration::from_secs(5));
+            }
             connection.noop()?;
-            thread::sleep(::std::time::Duration::from_secs(5));
         }
         println!("Syncing...");
-        connection.noop()?;
-        match connection.mail_number("INBOX")? {
-            a if a == i => continue,
-            a if a < i => i = a - 1,
-            _ => {}
-        }
-        println!("New mail");
-        i += 1;
-        let mail = connection.fetch_mail(i)?;
-        if apply_rules(&mail, &mut connection, &config, i)? {
-            i -= 1;
+    }
+}
+
+fn manage_account<P: AsRef<Path>>(path: P) -> Result<()> {
+    let config = Config::from_file(path)?;
+    let mut state = State::load(State::path_for(&config.account))?;
+    let mut backoff = Backoff::new(1, 300);
+    loop {
+        if let Err(e) = run_session(&config, &mut state, &mut backoff) {
+            // Refused login or invalid data would fail the same way again
+            if !e.is_connection() {
+                return Err(e);
+            }
+            let delay = backoff.next();
+            println!(
+                "[{}] connection lost: {}, reconnecting in {}s",
+                config.account.username,
+                e,
+                delay.as_secs()
+            );
+            thread::sleep(delay);
         }
     }
 }
@@ -107,9 +402,9 @@ fn manage_account<P: AsRef<Path>>(path: P) -> Result<()> {
 fn run_threads(accounts: Vec<String>) {
     let mut handlers = Vec::new();
     for account in accounts {
-        handlers.push(thread::spawn(move || match manage_account(account) {
+        handlers.push(thread::spawn(move || match manage_account(&account) {
             Ok(_) => {}
-            Err(e) => println!("{}", e),
+            Err(e) => println!("[{}] stopped: {}", account, e),
         }));
     }
 
@@ -119,7 +414,6 @@ fn run_threads(accounts: Vec<String>) {
 }
 
 // TODO test delete and else
-// TODO add any or every and else
 // TODO automatic imap
 // TODO account manager
 // TODO rule manager
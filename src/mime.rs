@@ -0,0 +1,400 @@
+use decode::decode_body;
+use html::html_to_text;
+use response::Value;
+
+/// Part of a mail, as described by imap BODYSTRUCTURE
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Part {
+    /// Lowercase type and subtype, like `text/plain`
+    pub mime_type: String,
+    /// Content-Type parameters, names are lowercase
+    pub params: Vec<(String, String)>,
+    /// Lowercase transfer encoding, like `base64`
+    pub encoding: String,
+    /// Lowercase disposition, like `attachment`
+    pub disposition: Option<String>,
+    /// Content-Disposition parameters, names are lowercase
+    pub disposition_params: Vec<(String, String)>,
+    /// Size in bytes of encoded content
+    pub size: u64,
+    /// Sub parts of a multipart
+    pub parts: Vec<Part>,
+}
+
+/// Read parameters list like `("CHARSET" "UTF-8" "NAME" "a.pdf")`
+fn params(value: Option<&Value>) -> Vec<(String, String)> {
+    let list = match value.and_then(Value::as_list) {
+        Some(list) => list,
+        None => return Vec::new(),
+    };
+    list.chunks(2)
+        .filter_map(|pair| match (pair.first(), pair.get(1)) {
+            (Some(name), Some(value)) => {
+                match (name.as_str(), value.as_str()) {
+                    (Some(name), Some(value)) => Some((name.to_lowercase(), value.to_string())),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Read disposition like `("ATTACHMENT" ("FILENAME" "a.pdf"))`
+fn disposition(value: Option<&Value>) -> (Option<String>, Vec<(String, String)>) {
+    match value.and_then(Value::as_list) {
+        Some(list) => {
+            (
+                list.first().and_then(Value::as_str).map(|d| d.to_lowercase()),
+                params(list.get(1)),
+            )
+        }
+        None => (None, Vec::new()),
+    }
+}
+
+/// Decode `%XX` escapes of an RFC 2231 parameter value
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::new();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' && idx + 2 < bytes.len() {
+            let high = (bytes[idx + 1] as char).to_digit(16);
+            let low = (bytes[idx + 2] as char).to_digit(16);
+            if let (Some(high), Some(low)) = (high, low) {
+                decoded.push((high * 16 + low) as u8);
+                idx += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[idx]);
+        idx += 1;
+    }
+    decoded
+}
+
+/// Get parameter value, with RFC 2231 continuations (`name*0`, `name*1`...) and encoding (`name*`)
+pub fn param(params: &[(String, String)], name: &str) -> Option<String> {
+    if let Some((_, value)) = params.iter().find(|&(n, _)| n == name) {
+        return Some(value.clone());
+    }
+    // Collect segments with their number, extended segments are percent encoded
+    let mut segments = Vec::new();
+    for (param, value) in params {
+        if !param.starts_with(name) || !param[name.len()..].starts_with('*') {
+            continue;
+        }
+        let suffix = &param[name.len() + 1..];
+        let (number, extended) = if suffix.is_empty() {
+            (Some(0), true)
+        } else if let Some(number) = suffix.strip_suffix('*') {
+            (number.parse::<u32>().ok(), true)
+        } else {
+            (suffix.parse::<u32>().ok(), false)
+        };
+        if let Some(number) = number {
+            segments.push((number, extended, value.as_str()));
+        }
+    }
+    if segments.is_empty() {
+        return None;
+    }
+    segments.sort_by_key(|&(number, _, _)| number);
+    let mut bytes = Vec::new();
+    for (idx, &(_, extended, value)) in segments.iter().enumerate() {
+        if !extended {
+            bytes.extend_from_slice(value.as_bytes());
+            continue;
+        }
+        // First extended segment starts with `charset'language'`, only utf-8 and ascii are known
+        let value = if idx == 0 {
+            value.splitn(3, '\'').nth(2).unwrap_or(value)
+        } else {
+            value
+        };
+        bytes.extend(percent_decode(value));
+    }
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+impl Part {
+    /// Read part from BODYSTRUCTURE value (RFC 3501 section 7.4.2)
+    pub fn from_bodystructure(value: &Value) -> Option<Part> {
+        let list = value.as_list()?;
+        if let Some(&Value::List(_)) = list.first() {
+            // Multipart: parts, subtype then extension data
+            let mut parts = Vec::new();
+            let mut idx = 0;
+            while let Some(part @ &Value::List(_)) = list.get(idx) {
+                if let Some(part) = Part::from_bodystructure(part) {
+                    parts.push(part);
+                }
+                idx += 1;
+            }
+            let subtype = list.get(idx).and_then(Value::as_str).unwrap_or("mixed");
+            let (disposition, disposition_params) = disposition(list.get(idx + 2));
+            return Some(Part {
+                mime_type: format!("multipart/{}", subtype.to_lowercase()),
+                params: params(list.get(idx + 1)),
+                encoding: String::new(),
+                disposition,
+                disposition_params,
+                size: 0,
+                parts,
+            });
+        }
+        let mime_type = match (
+            list.first().and_then(Value::as_str),
+            list.get(1).and_then(Value::as_str),
+        ) {
+            (Some(kind), Some(subtype)) => format!("{}/{}", kind, subtype).to_lowercase(),
+            _ => return None,
+        };
+        // Text parts have their number of lines, messages their envelope, body and lines
+        let extension = if mime_type.starts_with("text/") {
+            8
+        } else if mime_type == "message/rfc822" {
+            10
+        } else {
+            7
+        };
+        let (disposition, disposition_params) = disposition(list.get(extension + 1));
+        Some(Part {
+            mime_type,
+            params: params(list.get(2)),
+            encoding: list.get(5).and_then(Value::as_str).unwrap_or_default().to_lowercase(),
+            disposition,
+            disposition_params,
+            size: list.get(6).and_then(Value::as_number).unwrap_or(0),
+            parts: Vec::new(),
+        })
+    }
+
+    /// File name of part, from its disposition or its type
+    pub fn name(&self) -> Option<String> {
+        param(&self.disposition_params, "filename").or_else(|| param(&self.params, "name"))
+    }
+
+    /// Check if part is an attachment, rather than a text to display
+    pub fn is_attachment(&self) -> bool {
+        if !self.parts.is_empty() || self.mime_type.starts_with("multipart/") {
+            return false;
+        }
+        match self.disposition {
+            Some(ref disposition) if disposition == "attachment" => true,
+            Some(ref disposition) if disposition == "inline" => false,
+            _ => self.name().is_some() || self.mime_type == "message/rfc822",
+        }
+    }
+
+    /// Best text part to read in this part, with its imap section like `1.2`
+    ///
+    /// `section` is the section of this part, empty for the whole mail. In alternatives plain
+    /// text is preferred to html, in other multiparts the first text is the body.
+    fn body(&self, section: String) -> Option<(String, &Part)> {
+        if self.parts.is_empty() {
+            let text = self.mime_type == "text/plain" || self.mime_type == "text/html";
+            if text && !self.is_attachment() {
+                return Some((section, self));
+            }
+            return None;
+        }
+        let mut bodies = self.parts.iter().enumerate().filter_map(|(idx, part)| {
+            if section.is_empty() {
+                part.body((idx + 1).to_string())
+            } else {
+                part.body(format!("{}.{}", section, idx + 1))
+            }
+        });
+        if self.mime_type != "multipart/alternative" {
+            return bodies.next();
+        }
+        let bodies: Vec<(String, &Part)> = bodies.collect();
+        let plain = bodies.iter().position(|&(_, part)| part.mime_type == "text/plain");
+        bodies.into_iter().nth(plain.unwrap_or(0))
+    }
+
+    /// Text body of a mail having this structure, with its imap section
+    pub fn text_body(&self) -> Option<(String, &Part)> {
+        if self.parts.is_empty() {
+            self.body("1".to_string())
+        } else {
+            self.body(String::new())
+        }
+    }
+
+    /// Check if text of this part can be fetched from imap
+    ///
+    /// imap 0.4 panics on responses which aren't UTF-8, so 8bit parts are only fetched when their
+    /// charset is UTF-8 or ASCII.
+    pub fn is_fetchable(&self) -> bool {
+        if self.encoding != "8bit" && self.encoding != "binary" {
+            return true;
+        }
+        match param(&self.params, "charset") {
+            Some(charset) => {
+                let charset = charset.to_lowercase();
+                charset == "utf-8" || charset == "utf8" || charset == "us-ascii"
+            }
+            None => true,
+        }
+    }
+
+    /// Check if whole content of this part can be fetched from imap, see `is_fetchable`
+    ///
+    /// Binary parts and 8bit parts other than text in UTF-8 or ASCII may hold any byte.
+    pub fn is_raw_fetchable(&self) -> bool {
+        if !self.parts.is_empty() {
+            return self.parts.iter().all(Part::is_raw_fetchable);
+        }
+        match self.encoding.as_str() {
+            "binary" => false,
+            "8bit" => self.mime_type.starts_with("text/") && self.is_fetchable(),
+            _ => true,
+        }
+    }
+
+    /// Decode text of this part fetched from imap, html is converted to plain text
+    ///
+    /// Without charset, text is read as UTF-8.
+    pub fn decode(&self, text: &str) -> String {
+        let charset = param(&self.params, "charset").unwrap_or_default();
+        let text = decode_body(text.as_bytes(), &self.encoding, &charset);
+        if self.mime_type == "text/html" {
+            html_to_text(&text)
+        } else {
+            text
+        }
+    }
+
+    /// Every attachment of this part and its sub parts
+    pub fn attachments(&self) -> Vec<&Part> {
+        if self.is_attachment() {
+            return vec![self];
+        }
+        self.parts.iter().flat_map(|p| p.attachments()).collect()
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use response::parse_fetch;
+
+    fn structure(bodystructure: &str) -> Part {
+        let lines = vec![format!("* 1 FETCH (UID 1 BODYSTRUCTURE {})\r\n", bodystructure)];
+        let fetches = parse_fetch(&lines).unwrap();
+        Part::from_bodystructure(fetches[0].get("BODYSTRUCTURE").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn mime_single_part() {
+        let part = structure("(\"TEXT\" \"PLAIN\" (\"CHARSET\" \"UTF-8\") NIL NIL \"7BIT\" 1152 23)");
+        assert_eq!(
+            part,
+            Part {
+                mime_type: "text/plain".to_string(),
+                params: vec![("charset".to_string(), "UTF-8".to_string())],
+                encoding: "7bit".to_string(),
+                size: 1152,
+                ..Part::default()
+            }
+        );
+        assert!(part.attachments().is_empty());
+    }
+
+    #[test]
+    fn mime_multipart() {
+        let part = structure(
+            "((\"TEXT\" \"PLAIN\" (\"CHARSET\" \"US-ASCII\") NIL NIL \"7BIT\" 10 1 NIL NIL NIL)\
+             ((\"TEXT\" \"HTML\" NIL NIL NIL \"7BIT\" 20 1 NIL (\"INLINE\" NIL) NIL)\
+             (\"IMAGE\" \"PNG\" (\"NAME\" \"logo.png\") NIL NIL \"BASE64\" 300 NIL (\"INLINE\" NIL) NIL) \
+             \"RELATED\" NIL NIL NIL)\
+             (\"APPLICATION\" \"PDF\" (\"NAME\" \"a.pdf\") NIL NIL \"BASE64\" 4000 NIL \
+             (\"ATTACHMENT\" (\"FILENAME\" \"Report.pdf\")) NIL)\
+             (\"MESSAGE\" \"RFC822\" NIL NIL NIL \"7BIT\" 500 NIL \
+             (\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" 5 1) 3 NIL NIL NIL) \
+             \"MIXED\" (\"BOUNDARY\" \"xyz\") NIL NIL)",
+        );
+        assert_eq!(part.mime_type, "multipart/mixed");
+        assert_eq!(part.params, vec![("boundary".to_string(), "xyz".to_string())]);
+        assert_eq!(part.parts.len(), 4);
+        assert_eq!(part.parts[1].mime_type, "multipart/related");
+        let attachments = part.attachments();
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[0].name(), Some("Report.pdf".to_string()));
+        assert_eq!(attachments[0].size, 4000);
+        assert_eq!(attachments[1].mime_type, "message/rfc822");
+        assert_eq!(attachments[1].name(), None);
+        assert_eq!(part.text_body(), Some(("1".to_string(), &part.parts[0])));
+        assert_eq!(part.parts[2].encoding, "base64");
+    }
+
+    #[test]
+    fn mime_param_rfc2231() {
+        let params = vec![
+            ("filename*0*".to_string(), "utf-8''r%C3%A9sum".to_string()),
+            ("filename*1".to_string(), "%e.pdf".to_string()),
+            ("name*".to_string(), "us-ascii'en'a%20b".to_string()),
+        ];
+        assert_eq!(param(&params, "filename"), Some("résum%e.pdf".to_string()));
+        assert_eq!(param(&params, "name"), Some("a b".to_string()));
+        assert_eq!(param(&params, "size"), None);
+    }
+
+    #[test]
+    fn mime_text_body() {
+        let single = structure("(\"TEXT\" \"HTML\" NIL NIL NIL \"7BIT\" 20 1)");
+        assert_eq!(single.text_body().map(|(s, p)| (s, p.mime_type.as_str())),
+                   Some(("1".to_string(), "text/html")));
+        // Html in related part of alternative, plain text after it
+        let alternative = structure(
+            "(((\"TEXT\" \"HTML\" NIL NIL NIL \"QUOTED-PRINTABLE\" 20 1)\
+             (\"IMAGE\" \"PNG\" (\"NAME\" \"logo.png\") NIL NIL \"BASE64\" 300) \"RELATED\")\
+             (\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" 10 1) \"ALTERNATIVE\")",
+        );
+        assert_eq!(alternative.text_body().map(|(s, p)| (s, p.mime_type.as_str())),
+                   Some(("2".to_string(), "text/plain")));
+        let html_only = Part {
+            mime_type: "multipart/mixed".to_string(),
+            parts: vec![
+                Part {
+                    mime_type: "text/plain".to_string(),
+                    disposition: Some("attachment".to_string()),
+                    ..Part::default()
+                },
+                alternative.parts[0].clone(),
+            ],
+            ..Part::default()
+        };
+        assert_eq!(html_only.text_body().map(|(s, p)| (s, p.mime_type.as_str())),
+                   Some(("2.1".to_string(), "text/html")));
+        assert_eq!(html_only.parts[0].text_body(), None);
+        assert_eq!(
+            html_only.parts[1].parts[0].decode("<p>caf=C3=A9</p>"),
+            "café"
+        );
+    }
+
+    #[test]
+    fn mime_raw_fetchable() {
+        let text = |encoding: &str, charset: &str| Part {
+            mime_type: "text/plain".to_string(),
+            params: vec![("charset".to_string(), charset.to_string())],
+            encoding: encoding.to_string(),
+            ..Part::default()
+        };
+        assert!(text("8bit", "utf-8").is_raw_fetchable());
+        assert!(text("quoted-printable", "iso-8859-1").is_raw_fetchable());
+        assert!(!text("8bit", "iso-8859-1").is_raw_fetchable());
+        assert!(!text("binary", "utf-8").is_raw_fetchable());
+        let mixed = structure(
+            "((\"TEXT\" \"PLAIN\" (\"CHARSET\" \"UTF-8\") NIL NIL \"8BIT\" 10 1)\
+             (\"MESSAGE\" \"RFC822\" NIL NIL NIL \"8BIT\" 500 NIL \
+             (\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" 5 1) 3) \"MIXED\")",
+        );
+        assert!(!mixed.is_raw_fetchable());
+        assert!(mixed.parts[0].is_raw_fetchable());
+    }
+}
@@ -0,0 +1,523 @@
+use error::*;
+
+/// Value found in an imap response
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Nil,
+    Number(u64),
+    Atom(String),
+    Text(String),
+    List(Vec<Value>),
+}
+
+impl Value {
+    /// Get value as number
+    pub fn as_number(&self) -> Option<u64> {
+        match *self {
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Get value as text, atoms and strings are both accepted
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::Atom(ref s) |
+            Value::Text(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Get value as list
+    pub fn as_list(&self) -> Option<&Vec<Value>> {
+        match *self {
+            Value::List(ref l) => Some(l),
+            _ => None,
+        }
+    }
+}
+
+/// Data returned by server for one message of a fetch command
+#[derive(Debug, PartialEq)]
+pub struct Fetch {
+    pub seq: u32,
+    pub items: Vec<(String, Value)>,
+}
+
+impl Fetch {
+    /// Get value of an item, name is case insensitive
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        let name = name.to_uppercase();
+        self.items.iter().find(|&(n, _)| *n == name).map(
+            |(_, v)| v,
+        )
+    }
+
+    /// Get value of the first item starting with given prefix
+    pub fn get_prefix(&self, prefix: &str) -> Option<&Value> {
+        let prefix = prefix.to_uppercase();
+        self.items
+            .iter()
+            .find(|&(n, _)| n.starts_with(&prefix))
+            .map(|(_, v)| v)
+    }
+
+    /// Get uid of message
+    pub fn uid(&self) -> Option<u32> {
+        self.get("UID").and_then(|v| v.as_number()).map(|n| n as u32)
+    }
+}
+
+/// Mailbox returned by a list command
+#[derive(Debug, PartialEq)]
+pub struct ListEntry {
+    pub attributes: Vec<String>,
+    pub delimiter: Option<String>,
+    pub name: String,
+}
+
+/// Reader over a raw imap response
+struct Parser<'a> {
+    data: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.data.as_bytes().get(self.pos).cloned()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(b' ') {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_line(&mut self) {
+        match self.data[self.pos..].find('\n') {
+            Some(idx) => self.pos += idx + 1,
+            None => self.pos = self.data.len(),
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<()> {
+        self.skip_spaces();
+        if self.peek() != Some(c) {
+            bail!(ErrorKind::InvalidResponse(self.data[self.pos..].to_string()));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Read an atom, brackets are kept with their content (ex: `BODY[HEADER.FIELDS (TO)]`)
+    fn atom(&mut self) -> &'a str {
+        let start = self.pos;
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                b'[' => depth += 1,
+                b']' => depth -= 1,
+                b' ' | b'(' | b')' | b'\r' | b'\n' if depth == 0 => break,
+                b'\r' | b'\n' => break,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    fn quoted(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let mut value = String::new();
+        let mut chars = self.data[self.pos..].char_indices();
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += idx + 1;
+                    return Ok(value);
+                }
+                '\\' => {
+                    if let Some((_, c)) = chars.next() {
+                        value.push(c);
+                    }
+                }
+                c => value.push(c),
+            }
+        }
+        bail!(ErrorKind::InvalidResponse(self.data.to_string()))
+    }
+
+    fn literal(&mut self) -> Result<String> {
+        self.expect(b'{')?;
+        let end = match self.data[self.pos..].find('}') {
+            Some(idx) => self.pos + idx,
+            None => bail!(ErrorKind::InvalidResponse(self.data.to_string())),
+        };
+        let len = self.data[self.pos..end].trim_end_matches('+').parse::<usize>()
+            .chain_err(|| ErrorKind::InvalidResponse(self.data.to_string()))?;
+        self.pos = end + 1;
+        if self.data[self.pos..].starts_with("\r\n") {
+            self.pos += 2;
+        } else if self.data[self.pos..].starts_with('\n') {
+            self.pos += 1;
+        }
+        let mut end = ::std::cmp::min(self.pos + len, self.data.len());
+        while !self.data.is_char_boundary(end) {
+            end += 1;
+        }
+        let value = self.data[self.pos..end].to_string();
+        self.pos = end;
+        Ok(value)
+    }
+
+    fn list(&mut self) -> Result<Vec<Value>> {
+        self.expect(b'(')?;
+        let mut values = Vec::new();
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some(b')') => {
+                    self.pos += 1;
+                    return Ok(values);
+                }
+                Some(_) => values.push(self.value()?),
+                None => bail!(ErrorKind::InvalidResponse(self.data.to_string())),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        self.skip_spaces();
+        match self.peek() {
+            Some(b'"') => Ok(Value::Text(self.quoted()?)),
+            Some(b'{') => Ok(Value::Text(self.literal()?)),
+            Some(b'(') => Ok(Value::List(self.list()?)),
+            Some(_) => {
+                let atom = self.atom();
+                if atom.is_empty() {
+                    bail!(ErrorKind::InvalidResponse(self.data[self.pos..].to_string()));
+                }
+                if atom.eq_ignore_ascii_case("NIL") {
+                    Ok(Value::Nil)
+                } else if let Ok(n) = atom.parse::<u64>() {
+                    Ok(Value::Number(n))
+                } else {
+                    Ok(Value::Atom(atom.to_string()))
+                }
+            }
+            None => bail!(ErrorKind::InvalidResponse(self.data.to_string())),
+        }
+    }
+
+    /// Parse items of a fetch response, after `* n FETCH `
+    fn fetch_items(&mut self) -> Result<Vec<(String, Value)>> {
+        self.expect(b'(')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some(b')') => {
+                    self.pos += 1;
+                    return Ok(items);
+                }
+                Some(_) => {
+                    let name = self.atom().to_uppercase();
+                    if name.is_empty() {
+                        bail!(ErrorKind::InvalidResponse(self.data[self.pos..].to_string()));
+                    }
+                    let value = self.value()?;
+                    items.push((name, value));
+                }
+                None => bail!(ErrorKind::InvalidResponse(self.data.to_string())),
+            }
+        }
+    }
+}
+
+/// Parse all fetch responses from lines returned by server
+pub fn parse_fetch(lines: &[String]) -> Result<Vec<Fetch>> {
+    let data = lines.concat();
+    let mut parser = Parser {
+        data: &data,
+        pos: 0,
+    };
+    let mut fetches = Vec::new();
+    while parser.pos < data.len() {
+        if !parser.data[parser.pos..].starts_with("* ") {
+            parser.skip_line();
+            continue;
+        }
+        parser.pos += 2;
+        let seq = parser.atom().parse::<u32>();
+        parser.skip_spaces();
+        let is_fetch = parser.atom().eq_ignore_ascii_case("FETCH");
+        match seq {
+            Ok(seq) if is_fetch => {
+                let items = parser.fetch_items()?;
+                fetches.push(Fetch {
+                    seq,
+                    items,
+                });
+            }
+            _ => {}
+        }
+        parser.skip_line();
+    }
+    Ok(fetches)
+}
+
+/// Parse all list responses from lines returned by server
+pub fn parse_list(lines: &[String]) -> Result<Vec<ListEntry>> {
+    let data = lines.concat();
+    let mut parser = Parser {
+        data: &data,
+        pos: 0,
+    };
+    let mut entries = Vec::new();
+    while parser.pos < data.len() {
+        if !parser.data[parser.pos..].starts_with("* ") {
+            parser.skip_line();
+            continue;
+        }
+        parser.pos += 2;
+        let kind = parser.atom();
+        if kind.eq_ignore_ascii_case("LIST") || kind.eq_ignore_ascii_case("LSUB") {
+            let attributes = parser
+                .list()?
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|a| a.to_string())
+                .collect();
+            let delimiter = parser.value()?.as_str().map(|d| d.to_string());
+            let name = match parser.value()? {
+                Value::Number(n) => n.to_string(),
+                value => value.as_str().unwrap_or_default().to_string(),
+            };
+            entries.push(ListEntry {
+                attributes,
+                delimiter,
+                name,
+            });
+        }
+        parser.skip_line();
+    }
+    Ok(entries)
+}
+
+/// Collect numbers of a value, nested lists included
+fn flatten_numbers(value: &Value, numbers: &mut Vec<u32>) {
+    match *value {
+        Value::Number(n) => numbers.push(n as u32),
+        Value::List(ref values) => {
+            for value in values {
+                flatten_numbers(value, numbers);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Parse thread responses from lines returned by server (RFC 5256)
+///
+/// Each thread is returned as the list of its messages, the tree inside a thread isn't kept.
+pub fn parse_thread(lines: &[String]) -> Result<Vec<Vec<u32>>> {
+    let data = lines.concat();
+    let mut parser = Parser {
+        data: &data,
+        pos: 0,
+    };
+    let mut threads = Vec::new();
+    while parser.pos < data.len() {
+        if !parser.data[parser.pos..].starts_with("* ") {
+            parser.skip_line();
+            continue;
+        }
+        parser.pos += 2;
+        if parser.atom().eq_ignore_ascii_case("THREAD") {
+            loop {
+                parser.skip_spaces();
+                if parser.peek() != Some(b'(') {
+                    break;
+                }
+                let mut thread = Vec::new();
+                flatten_numbers(&Value::List(parser.list()?), &mut thread);
+                threads.push(thread);
+            }
+        }
+        parser.skip_line();
+    }
+    Ok(threads)
+}
+
+/// Parse search responses from lines returned by server
+pub fn parse_search(lines: &[String]) -> Vec<u32> {
+    let data = lines.concat();
+    let mut parser = Parser {
+        data: &data,
+        pos: 0,
+    };
+    let mut numbers = Vec::new();
+    while parser.pos < data.len() {
+        if !parser.data[parser.pos..].starts_with("* ") {
+            parser.skip_line();
+            continue;
+        }
+        parser.pos += 2;
+        if parser.atom().eq_ignore_ascii_case("SEARCH") {
+            loop {
+                parser.skip_spaces();
+                match parser.atom().parse::<u32>() {
+                    Ok(n) => numbers.push(n),
+                    Err(_) => break,
+                }
+            }
+        }
+        parser.skip_line();
+    }
+    numbers
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn fetch_uid() {
+        let fetches = parse_fetch(&lines(
+            &[
+                "* 1 FETCH (UID 42)\r\n",
+                "* 2 FETCH (UID 43)\r\n",
+                "a1 OK Success\r\n",
+            ],
+        )).unwrap();
+        assert_eq!(fetches.len(), 2);
+        assert_eq!(fetches[0].seq, 1);
+        assert_eq!(fetches[0].uid(), Some(42));
+        assert_eq!(fetches[1].uid(), Some(43));
+    }
+
+    #[test]
+    fn fetch_size_and_date() {
+        let fetches = parse_fetch(&lines(
+            &[
+                "* 1 FETCH (UID 42 INTERNALDATE \" 1-Jul-2003 10:52:37 +0200\" RFC822.SIZE 2048)\r\n",
+                "a1 OK Success\r\n",
+            ],
+        )).unwrap();
+        assert_eq!(
+            fetches[0].get("INTERNALDATE").and_then(Value::as_str),
+            Some(" 1-Jul-2003 10:52:37 +0200")
+        );
+        assert_eq!(
+            fetches[0].get("RFC822.SIZE").and_then(Value::as_number),
+            Some(2048)
+        );
+    }
+
+    #[test]
+    fn fetch_literal() {
+        let fetches = parse_fetch(&lines(
+            &[
+                "* 3 FETCH (BODY[HEADER.FIELDS (FROM SUBJECT)] {27}\r\n",
+                "Subject: Hello\r\n",
+                "From: a\r\n",
+                "\r\n",
+                " UID 7)\r\n",
+                "a2 OK Success\r\n",
+            ],
+        )).unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].uid(), Some(7));
+        assert_eq!(
+            fetches[0].get("body[header.fields (from subject)]"),
+            Some(&Value::Text("Subject: Hello\r\nFrom: a\r\n\r\n".to_string()))
+        );
+        assert!(fetches[0].get_prefix("BODY[HEADER").is_some());
+    }
+
+    #[test]
+    fn fetch_list_and_quoted() {
+        let fetches = parse_fetch(&lines(
+            &[
+                "* 1 FETCH (FLAGS (\\Seen $Label1) INTERNALDATE \"17-Jul-1996 02:44:25 -0700\" \
+                 BODY[1] NIL)\r\n",
+            ],
+        )).unwrap();
+        assert_eq!(
+            fetches[0].get("FLAGS"),
+            Some(&Value::List(vec![
+                Value::Atom("\\Seen".to_string()),
+                Value::Atom("$Label1".to_string()),
+            ]))
+        );
+        assert_eq!(
+            fetches[0].get("INTERNALDATE").and_then(|v| v.as_str()),
+            Some("17-Jul-1996 02:44:25 -0700")
+        );
+        assert_eq!(fetches[0].get("BODY[1]"), Some(&Value::Nil));
+    }
+
+    #[test]
+    fn list_entries() {
+        let entries = parse_list(&lines(
+            &[
+                "* LIST (\\HasNoChildren) \"/\" \"INBOX\"\r\n",
+                "* LIST (\\HasNoChildren \\Trash) \"/\" \"[Gmail]/Trash\"\r\n",
+                "* LIST (\\Noselect) NIL Archive\r\n",
+                "a4 OK Success\r\n",
+            ],
+        )).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[1],
+            ListEntry {
+                attributes: vec!["\\HasNoChildren".to_string(), "\\Trash".to_string()],
+                delimiter: Some("/".to_string()),
+                name: "[Gmail]/Trash".to_string(),
+            }
+        );
+        assert_eq!(entries[2].delimiter, None);
+        assert_eq!(entries[2].name, "Archive");
+    }
+
+    #[test]
+    fn fetch_ignore_other_responses() {
+        let fetches = parse_fetch(&lines(
+            &[
+                "* 4 EXISTS\r\n",
+                "* 1 FETCH (UID 1)\r\n",
+                "* 2 EXPUNGE\r\n",
+                "a3 OK Success\r\n",
+            ],
+        )).unwrap();
+        assert_eq!(fetches.len(), 1);
+    }
+
+    #[test]
+    fn thread_responses() {
+        let threads = parse_thread(&lines(
+            &[
+                "* THREAD (2)(3 6 (4 23)(44 7 96))((5)(8 9))\r\n",
+                "a5 OK Thread completed\r\n",
+            ],
+        )).unwrap();
+        assert_eq!(
+            threads,
+            vec![vec![2], vec![3, 6, 4, 23, 44, 7, 96], vec![5, 8, 9]]
+        );
+        assert!(parse_thread(&lines(&["* THREAD\r\n"])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn search_responses() {
+        let uids = parse_search(&lines(
+            &["* SEARCH 2 84 882\r\n", "* 3 EXISTS\r\n", "a4 OK Search completed\r\n"],
+        ));
+        assert_eq!(uids, vec![2, 84, 882]);
+        assert!(parse_search(&lines(&["* SEARCH\r\n"])).is_empty());
+    }
+}
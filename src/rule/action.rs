@@ -1,5 +1,29 @@
+use chrono::Utc;
+use compose;
+use config::Config;
 use connection::Connection;
 use error::*;
+use folder::{self, SpecialUse};
+use mail::Mail;
+use response::ListEntry;
+use smtp::Smtp;
+use state::State;
+use threading;
+
+/// Trash used if server doesn't advertise one and config doesn't give one
+const DEFAULT_TRASH: &str = "Trash";
+
+/// Days before replying again to the same address, by default
+const DEFAULT_REPLY_INTERVAL: u64 = 7;
+
+/// How a mail is forwarded
+#[derive(Debug, PartialEq)]
+enum ForwardMode {
+    /// Original mail is sent as is, with Resent- headers
+    Inline,
+    /// Original mail is attached to a new mail
+    Attachment,
+}
 
 /// Describe action type
 #[derive(Debug, PartialEq)]
@@ -9,13 +33,14 @@ enum ActionType {
     MoveTo(String),
     Delete,
     PermanentDelete,
-    ForwardTo(String),
+    ForwardTo(String, ForwardMode),
     ReplyWith(String),
     SetFlag(String),
     RemoveFlag(String),
-    ClearFlags,
+    ClearFlags(Vec<String>),
     MarkAsImportant,
     MarkAsRead,
+    MuteThread,
 }
 
 impl ActionType {
@@ -24,89 +49,247 @@ impl ActionType {
         let action = action.as_ref().trim();
         if action == "no more rules" {
             Ok(ActionType::NoMoreRules)
-        } else if action.starts_with("copy to ") {
-            Ok(ActionType::CopyTo(action[8..].to_string()))
-        } else if action.starts_with("move to ") {
-            Ok(ActionType::MoveTo(action[8..].to_string()))
+        } else if let Some(folder) = action.strip_prefix("copy to ") {
+            Ok(ActionType::CopyTo(folder.to_string()))
+        } else if let Some(folder) = action.strip_prefix("move to ") {
+            Ok(ActionType::MoveTo(folder.to_string()))
         } else if action == "delete" {
             Ok(ActionType::Delete)
         } else if action == "permanent delete" {
             Ok(ActionType::PermanentDelete)
-        } else if action.starts_with("forward to ") {
-            Ok(ActionType::ForwardTo(action[11..].to_string()))
-        } else if action.starts_with("reply with ") {
-            Ok(ActionType::ReplyWith(action[11..].to_string()))
-        } else if action.starts_with("set flag ") {
-            Ok(ActionType::SetFlag(action[9..].to_string()))
-        } else if action.starts_with("remove flag ") {
-            Ok(ActionType::RemoveFlag(action[12..].to_string()))
+        } else if let Some(dest) = action.strip_prefix("forward to ") {
+            let (dest, mode) = match dest.strip_suffix("as attachment") {
+                Some(dest) if dest.is_empty() || dest.ends_with(char::is_whitespace) => {
+                    (dest.trim(), ForwardMode::Attachment)
+                }
+                _ => (dest.trim(), ForwardMode::Inline),
+            };
+            // A single address is expected, anything else would reach smtp server as is
+            if !dest.contains('@') || dest.contains(char::is_whitespace) {
+                bail!(ErrorKind::InvalidAction(action.to_string()));
+            }
+            Ok(ActionType::ForwardTo(dest.to_string(), mode))
+        } else if let Some(text) = action.strip_prefix("reply with ") {
+            Ok(ActionType::ReplyWith(text.to_string()))
+        } else if let Some(flag) = action.strip_prefix("set flag ") {
+            Ok(ActionType::SetFlag(flag.to_string()))
+        } else if let Some(flag) = action.strip_prefix("remove flag ") {
+            Ok(ActionType::RemoveFlag(flag.to_string()))
         } else if action == "clear flags" {
-            Ok(ActionType::ClearFlags)
+            Ok(ActionType::ClearFlags(Vec::new()))
+        } else if let Some(flags) = action.strip_prefix("clear flags except ") {
+            Ok(ActionType::ClearFlags(
+                flags
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|f| !f.is_empty())
+                    .map(|f| f.to_string())
+                    .collect(),
+            ))
         } else if action == "mark as important" {
             Ok(ActionType::MarkAsImportant)
         } else if action == "mark as read" {
             Ok(ActionType::MarkAsRead)
+        } else if action == "mute thread" {
+            Ok(ActionType::MuteThread)
         } else {
             bail!(ErrorKind::InvalidAction(action.to_string()));
         }
     }
 
-    /// Apply action to mail
-    fn apply(&self, connection: &mut Connection, idx: usize) -> Result<()> {
+    /// Apply action to mail of mailbox, flags of mail are updated so following rules can check them
+    ///
+    /// Copying or moving mail to its own mailbox is skipped: the copy would be a new mail, and
+    /// rules would process it again and again.
+    fn apply(
+        &self,
+        connection: &mut Connection,
+        config: &Config,
+        state: &mut State,
+        mailbox: &ListEntry,
+        mail: &mut Mail,
+    ) -> Result<()> {
+        let uid = mail.uid.to_string();
         match self {
-            &ActionType::NoMoreRules => Ok(()),
-            &ActionType::CopyTo(ref folder) => {
-                connection.create(folder)?;
-                connection.copy(&idx.to_string(), folder)?;
-                Ok(())
-            }
-            &ActionType::MoveTo(ref folder) => {
-                connection.create(folder)?;
-                connection.copy(&idx.to_string(), folder)?;
-                connection.store(&idx.to_string(), "+flags (\\deleted)")?;
-                connection.expunge()?;
+            ActionType::NoMoreRules => Ok(()),
+            ActionType::CopyTo(folder) => {
+                let resolved = resolve_folder(connection, folder)?;
+                if is_current(mailbox, &resolved) {
+                    return Ok(());
+                }
+                connection.create(&resolved, mailbox.delimiter.as_deref())?;
+                connection.uid_copy(&uid, &resolved)?;
+                record_folder(mail, state, folder);
                 Ok(())
             }
-            &ActionType::Delete => {
-                connection.store(&idx.to_string(), "+flags (\\deleted)")?;
-                // TODO find trash with list, look at flags
-                connection.copy(&idx.to_string(), "TRASH")?;
-                connection.expunge()?;
+            ActionType::MoveTo(folder) => {
+                let resolved = resolve_folder(connection, folder)?;
+                if is_current(mailbox, &resolved) {
+                    return Ok(());
+                }
+                connection.create(&resolved, mailbox.delimiter.as_deref())?;
+                connection.move_mail(mail.uid, &resolved)?;
+                record_folder(mail, state, folder);
                 Ok(())
             }
-            &ActionType::PermanentDelete => {
-                connection.store(&idx.to_string(), "+flags (\\deleted)")?;
-                connection.expunge()?;
-                Ok(())
+            ActionType::Delete => {
+                let trash = connection
+                    .special_folder(SpecialUse::Trash)
+                    .unwrap_or(DEFAULT_TRASH)
+                    .to_string();
+                if is_current(mailbox, &trash) {
+                    return Ok(());
+                }
+                connection.create(&trash, mailbox.delimiter.as_deref())?;
+                connection.move_mail(mail.uid, &trash)
             }
-            &ActionType::SetFlag(ref flag) => {
-                connection.store(
-                    &idx.to_string(),
+            ActionType::PermanentDelete => connection.delete_mail(mail.uid),
+            ActionType::SetFlag(flag) => {
+                connection.uid_store(
+                    &uid,
                     &format!("+flags ({})", flag),
                 )?;
+                mail.add_flags(flag);
                 Ok(())
             }
-            &ActionType::RemoveFlag(ref flag) => {
-                connection.store(
-                    &idx.to_string(),
+            ActionType::RemoveFlag(flag) => {
+                connection.uid_store(
+                    &uid,
                     &format!("-flags ({})", flag),
                 )?;
+                mail.remove_flags(flag);
                 Ok(())
             }
-            &ActionType::ClearFlags => {
-                // TODO connection.store(idx.to_string(), &format!("+flags ({})", flag));
+            ActionType::ClearFlags(except) => {
+                let mut preserved = except.clone();
+                if let Some(ref flags) = config.account.preserved_flags {
+                    preserved.extend(flags.iter().cloned());
+                }
+                let flags = flags_to_clear(&connection.fetch_flags(mail.uid)?, &preserved);
+                if !flags.is_empty() {
+                    connection.uid_store(
+                        &uid,
+                        &format!("-flags.silent ({})", flags.join(" ")),
+                    )?;
+                    mail.remove_flags(&flags.join(" "));
+                }
                 Ok(())
             }
-            &ActionType::MarkAsImportant => connection.copy(&idx.to_string(), "Important"),
-            &ActionType::MarkAsRead => {
-                connection.store(&idx.to_string(), "+flags (\\seen)")?;
+            ActionType::MarkAsImportant => {
+                match connection.special_folder(SpecialUse::Important).map(
+                    |f| f.to_string(),
+                ) {
+                    Some(ref important) if is_current(mailbox, important) => {}
+                    Some(important) => connection.uid_copy(&uid, &important)?,
+                    None => {
+                        connection.uid_store(&uid, "+flags (\\flagged)")?;
+                        mail.add_flags("\\Flagged");
+                    }
+                }
                 Ok(())
             }
-            _ => unimplemented!(),
+            ActionType::MarkAsRead => {
+                connection.uid_store(&uid, "+flags (\\seen)")?;
+                mail.add_flags("\\Seen");
+                Ok(())
+            }
+            ActionType::MuteThread => {
+                if !threading::update(mail, state, |thread| thread.muted = true) {
+                    println!("no thread to mute for \"{}\": mail has no message id", mail.subject);
+                }
+                Ok(())
+            }
+            ActionType::ForwardTo(dest, mode) => {
+                let (smtp, from) = smtp(config)?;
+                let raw = connection.fetch_raw(mail)?;
+                let message = match *mode {
+                    ForwardMode::Inline => compose::forward_inline(&raw, from, dest),
+                    ForwardMode::Attachment => compose::forward_attached(&raw, mail, from, dest),
+                };
+                smtp.send(from, &[dest], &message)
+            }
+            ActionType::ReplyWith(text) => {
+                let (smtp, from) = smtp(config)?;
+                let mut own = vec![from, &config.account.username];
+                if let Some(ref username) = smtp.username {
+                    own.push(username);
+                }
+                if let Some(reason) = compose::reply_refusal(mail, &own) {
+                    println!("no reply to \"{}\": {}", mail.subject, reason);
+                    return Ok(());
+                }
+                let to = match compose::reply_address(mail) {
+                    Some(to) => to,
+                    None => return Ok(()),
+                };
+                let interval = config.account.reply_interval.unwrap_or(
+                    DEFAULT_REPLY_INTERVAL,
+                ) as i64 * 24 * 3600;
+                let now = Utc::now().timestamp();
+                if state.replied_recently(&to, interval, now) {
+                    println!("no reply to \"{}\": {} already got a reply", mail.subject, to);
+                    return Ok(());
+                }
+                smtp.send(from, &[&to], &compose::reply(mail, from, &to, text))?;
+                state.record_reply(&to, interval, now);
+                state.save()
+            }
         }
     }
 }
 
+/// Get mailbox name, special uses like `\Junk` are replaced by the mailbox having this use
+fn resolve_folder(connection: &Connection, folder: &str) -> Result<String> {
+    match SpecialUse::from_attribute(folder) {
+        Some(special_use) => {
+            match connection.special_folder(special_use) {
+                Some(name) => Ok(name.to_string()),
+                None => bail!(ErrorKind::MissingFolder(folder.to_string())),
+            }
+        }
+        None => Ok(folder.to_string()),
+    }
+}
+
+/// Check if folder is the mailbox whose mails are processed
+fn is_current(mailbox: &ListEntry, folder: &str) -> bool {
+    let same = folder::same_mailbox(&mailbox.name, folder, mailbox.delimiter.as_deref());
+    if same {
+        println!("mail is already in {}, action skipped", folder);
+    }
+    same
+}
+
+/// Remember in thread of mail the folder it was moved or copied to, as written in action
+fn record_folder(mail: &mut Mail, state: &mut State, folder: &str) {
+    threading::update(mail, state, |thread| {
+        if !thread.folders.iter().any(|f| f == folder) {
+            thread.folders.push(folder.to_string());
+        }
+    });
+}
+
+/// Get flags to remove from a mail to clear them, except preserved ones
+///
+/// `\Recent` is kept since only server can change it.
+fn flags_to_clear(flags: &[String], preserved: &[String]) -> Vec<String> {
+    flags
+        .iter()
+        .filter(|flag| {
+            !flag.eq_ignore_ascii_case("\\Recent") &&
+                !preserved.iter().any(|p| p.eq_ignore_ascii_case(flag))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Get smtp server and address to send mails from
+fn smtp(config: &Config) -> Result<(&Smtp, &str)> {
+    match config.smtp {
+        Some(ref smtp) => Ok((smtp, smtp.from.as_ref().unwrap_or(&config.account.username))),
+        None => bail!(ErrorKind::MissingSmtp),
+    }
+}
+
 /// Action structure to apply
 #[derive(Debug, PartialEq)]
 pub struct Action(ActionType);
@@ -118,30 +301,50 @@ impl Action {
         Ok(Action(act))
     }
 
-    /// Apply action to mail
-    pub fn apply(&self, connection: &mut Connection, idx: usize) -> Result<()> {
-        self.0.apply(connection, idx)
+    /// Create action moving mails to folder
+    pub fn move_to(folder: String) -> Action {
+        Action(ActionType::MoveTo(folder))
+    }
+
+    /// Apply action to mail of mailbox
+    pub fn apply(
+        &self,
+        connection: &mut Connection,
+        config: &Config,
+        state: &mut State,
+        mailbox: &ListEntry,
+        mail: &mut Mail,
+    ) -> Result<()> {
+        self.0.apply(connection, config, state, mailbox, mail)
     }
 
     /// Check if action remove mail
     pub fn is_remove(&self) -> bool {
-        match self.0 {
-            ActionType::MoveTo(_) |
-            ActionType::PermanentDelete |
-            ActionType::Delete => true,
-            _ => false,
-        }
+        matches!(
+            self.0,
+            ActionType::MoveTo(_) | ActionType::PermanentDelete | ActionType::Delete
+        )
     }
 
     /// Check if action stop other rules
     pub fn is_rules_stop(&self) -> bool {
         self.0 == ActionType::NoMoreRules
     }
+
+    /// Special use of the folder a mail is copied or moved to, like `\Junk`
+    pub fn special_use(&self) -> Option<SpecialUse> {
+        match self.0 {
+            ActionType::CopyTo(ref folder) |
+            ActionType::MoveTo(ref folder) => SpecialUse::from_attribute(folder),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
 mod unit_tests {
-    use super::ActionType;
+    use super::{flags_to_clear, Action, ActionType, ForwardMode};
+    use folder::SpecialUse;
 
     #[test]
     fn action_no_rules() {
@@ -208,7 +411,7 @@ mod unit_tests {
     }
 
     fn action_forward_helper(dest: &str) {
-        let test_val = ActionType::ForwardTo(dest.to_string());
+        let test_val = ActionType::ForwardTo(dest.to_string(), ForwardMode::Inline);
         assert_eq!(
             test_val,
             ActionType::parse(&format!("forward to {}", dest)).unwrap(),
@@ -222,6 +425,35 @@ mod unit_tests {
         action_forward_helper("test@test.com");
     }
 
+    #[test]
+    fn action_special_use() {
+        assert_eq!(Action::new("move to \\Junk").unwrap().special_use(), Some(SpecialUse::Junk));
+        assert_eq!(
+            Action::new("copy to \\archive").unwrap().special_use(),
+            Some(SpecialUse::Archive)
+        );
+        assert_eq!(Action::new("move to Junk").unwrap().special_use(), None);
+        assert_eq!(Action::new("delete").unwrap().special_use(), None);
+    }
+
+    #[test]
+    fn action_forward_attachment() {
+        assert_eq!(
+            ActionType::parse("forward to test@test.com as attachment").unwrap(),
+            ActionType::ForwardTo("test@test.com".to_string(), ForwardMode::Attachment),
+            "fail with forward as attachment"
+        );
+        for action in &[
+            "forward to as attachment",
+            "forward to  as attachment",
+            "forward to nobody",
+            "forward to a@b.com, c@d.com",
+        ]
+        {
+            assert!(ActionType::parse(action).is_err(), "{} should fail", action);
+        }
+    }
+
     fn action_reply_helper(dest: &str) {
         let test_val = ActionType::ReplyWith(dest.to_string());
         assert_eq!(
@@ -274,9 +506,30 @@ mod unit_tests {
     fn action_clear_flag() {
         assert_eq!(
             ActionType::parse("clear flags").unwrap(),
-            ActionType::ClearFlags,
+            ActionType::ClearFlags(Vec::new()),
             "fail with clear flags"
         );
+        assert_eq!(
+            ActionType::parse("clear flags except \\Seen, $Label1").unwrap(),
+            ActionType::ClearFlags(vec!["\\Seen".to_string(), "$Label1".to_string()]),
+            "fail with clear flags except"
+        );
+    }
+
+    #[test]
+    fn action_flags_to_clear() {
+        let flags: Vec<String> = vec!["\\Seen", "\\Recent", "\\Flagged", "$Label1"]
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(
+            flags_to_clear(&flags, &[]),
+            vec!["\\Seen", "\\Flagged", "$Label1"]
+        );
+        assert_eq!(
+            flags_to_clear(&flags, &["\\seen".to_string()]),
+            vec!["\\Flagged", "$Label1"]
+        );
     }
 
     #[test]
@@ -296,4 +549,13 @@ mod unit_tests {
             "fail with mark as read"
         );
     }
+
+    #[test]
+    fn action_mute_thread() {
+        assert_eq!(
+            ActionType::parse("mute thread").unwrap(),
+            ActionType::MuteThread,
+            "fail with mute thread"
+        );
+    }
 }
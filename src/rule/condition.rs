@@ -1,23 +1,218 @@
+use caseless::default_case_fold_str;
+use chrono::Duration;
+use decode::{decode_words, normalize};
 use error::*;
-use mail::{Mail, MailAddress};
+use glob::Pattern;
+use mail::{normalize_flag, Mail, MailAddress};
+use regex::{Regex, RegexBuilder};
+use rule::Context;
+use rule::date::DateCondition;
 
-#[derive(Debug, PartialEq)]
+/// Names of checkers, multi-word names are matched word by word
+const CHECKERS: [&str; 6] = [
+    "is",
+    "contains",
+    "starts with",
+    "ends with",
+    "matches",
+    "glob",
+];
+
+/// Natural names of negated checkers, any checker can also be negated with a leading `not`
+const NEGATED_CHECKERS: [(&str, &str); 5] = [
+    ("is not", "is"),
+    ("does not contain", "contains"),
+    ("does not start with", "starts with"),
+    ("does not end with", "ends with"),
+    ("does not match", "matches"),
+];
+
+/// Modifier following checker name to make it case insensitive
+const CASE_INSENSITIVE: &str = "(i)";
+
+#[derive(Debug)]
 enum ConditionChecker {
     Contains,
     Is,
+    StartsWith,
+    EndsWith,
+    /// Regex is compiled when condition is parsed
+    Matches(Regex),
+    /// Shell-style pattern, compiled when condition is parsed
+    Glob(Pattern),
+    /// Compare both texts after Unicode case folding
+    CaseInsensitive(Box<ConditionChecker>),
+    /// Negation of a checker, for addresses and headers it means that no value matches
+    Not(Box<ConditionChecker>),
+    /// At least one value matches, it lets negated checkers mean that some value doesn't match
+    Any(Box<ConditionChecker>),
+    /// Every value matches
+    Every(Box<ConditionChecker>),
+}
+
+impl PartialEq for ConditionChecker {
+    fn eq(&self, other: &ConditionChecker) -> bool {
+        match (self, other) {
+            (&ConditionChecker::Contains, &ConditionChecker::Contains) |
+            (&ConditionChecker::Is, &ConditionChecker::Is) |
+            (&ConditionChecker::StartsWith, &ConditionChecker::StartsWith) |
+            (&ConditionChecker::EndsWith, &ConditionChecker::EndsWith) => true,
+            (ConditionChecker::Matches(a), ConditionChecker::Matches(b)) => {
+                a.as_str() == b.as_str()
+            }
+            (ConditionChecker::Glob(a), ConditionChecker::Glob(b)) => a == b,
+            (ConditionChecker::CaseInsensitive(a),
+             ConditionChecker::CaseInsensitive(b)) => a == b,
+            (&ConditionChecker::Not(ref a), &ConditionChecker::Not(ref b)) |
+            (&ConditionChecker::Any(ref a), &ConditionChecker::Any(ref b)) |
+            (&ConditionChecker::Every(ref a), &ConditionChecker::Every(ref b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Comparison of a number, like a size or an age
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    /// Parse operator at start of text and return it with the remaining text
+    pub fn parse(text: &str) -> Option<(Comparison, &str)> {
+        let operators = [
+            (">=", Comparison::GreaterOrEqual),
+            ("<=", Comparison::LessOrEqual),
+            (">", Comparison::Greater),
+            ("<", Comparison::Less),
+        ];
+        let text = text.trim_start();
+        operators
+            .iter()
+            .find(|&&(op, _)| text.starts_with(op))
+            .map(|&(op, comparison)| (comparison, text[op.len()..].trim_start()))
+    }
+
+    /// Compare value with limit
+    pub fn compare<T: PartialOrd>(&self, value: &T, limit: &T) -> bool {
+        match *self {
+            Comparison::Less => value < limit,
+            Comparison::LessOrEqual => value <= limit,
+            Comparison::Greater => value > limit,
+            Comparison::GreaterOrEqual => value >= limit,
+        }
+    }
+}
+
+/// Parse size like `10MB`, units are `B`, `KB`, `MB` and `GB` (powers of 1024), case insensitive
+fn parse_size(text: &str) -> Option<u64> {
+    let text = text.split_whitespace().collect::<String>().to_uppercase();
+    let idx = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let count = match text[..idx].parse::<u64>() {
+        Ok(count) => count,
+        Err(_) => return None,
+    };
+    let unit = match &text[idx..] {
+        "" | "B" => 1,
+        "KB" => 1024,
+        "MB" => 1024 * 1024,
+        "GB" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    count.checked_mul(unit)
+}
+
+/// Strip leading words from text, words must be followed by whitespace or end of text
+///
+/// Remaining text is returned without its leading whitespace.
+fn strip_words<'a>(text: &'a str, words: &str) -> Option<&'a str> {
+    let mut rest = text.trim_start();
+    for word in words.split_whitespace() {
+        if !rest.starts_with(word) {
+            return None;
+        }
+        let after = &rest[word.len()..];
+        if !after.is_empty() && !after.starts_with(char::is_whitespace) {
+            return None;
+        }
+        rest = after.trim_start();
+    }
+    Some(rest)
 }
 
 impl ConditionChecker {
-    /// Parse condition checker
-    fn parse<S: AsRef<str>>(checker: S) -> Result<ConditionChecker> {
-        let checker = checker.as_ref();
-        if checker == "is" {
-            Ok(ConditionChecker::Is)
-        } else if checker == "contains" {
-            Ok(ConditionChecker::Contains)
+    /// Parse checker at start of text, like `starts with (i) hello`, and return it with the value
+    fn parse(text: &str) -> Result<(ConditionChecker, String)> {
+        let (negated, text) = match strip_words(text, "not") {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let alias = if negated {
+            None
         } else {
-            bail!(ErrorKind::InvalidConditionChecker(checker.to_string()));
+            NEGATED_CHECKERS
+                .iter()
+                .filter_map(|&(alias, name)| strip_words(text, alias).map(|rest| (name, rest)))
+                .next()
+        };
+        let (negated, name, rest) = match alias {
+            Some((name, rest)) => (true, name, rest),
+            None => {
+                match CHECKERS
+                    .iter()
+                    .filter_map(|name| strip_words(text, name).map(|rest| (*name, rest)))
+                    .next() {
+                    Some((name, rest)) => (negated, name, rest),
+                    None => {
+                        let word = text.split_whitespace().next().unwrap_or_default();
+                        bail!(ErrorKind::InvalidConditionChecker(word.to_string()));
+                    }
+                }
+            }
+        };
+        let (case_insensitive, value) = match strip_words(rest, CASE_INSENSITIVE) {
+            Some(value) => (true, value),
+            None => (false, rest),
+        };
+        // Mail texts are normalized too
+        let normalized = normalize(value);
+        let value = normalized.as_str();
+        // Patterns are compiled from folded value, so they match folded texts
+        let pattern = if case_insensitive && name != "matches" {
+            default_case_fold_str(value)
+        } else {
+            value.to_string()
+        };
+        let mut checker = match name {
+            "is" => ConditionChecker::Is,
+            "contains" => ConditionChecker::Contains,
+            "starts with" => ConditionChecker::StartsWith,
+            "ends with" => ConditionChecker::EndsWith,
+            "matches" => {
+                // Regex handles case insensitivity itself, folding could change its meaning
+                match RegexBuilder::new(value).case_insensitive(case_insensitive).build() {
+                    Ok(regex) => ConditionChecker::Matches(regex),
+                    Err(e) => bail!(ErrorKind::InvalidRegex(value.to_string(), e.to_string())),
+                }
+            }
+            "glob" => {
+                match Pattern::new(&pattern) {
+                    Ok(glob) => ConditionChecker::Glob(glob),
+                    Err(e) => bail!(ErrorKind::InvalidGlob(value.to_string(), e.to_string())),
+                }
+            }
+            _ => unreachable!(),
+        };
+        if case_insensitive && name != "matches" {
+            checker = ConditionChecker::CaseInsensitive(Box::new(checker));
         }
+        if negated {
+            checker = ConditionChecker::Not(Box::new(checker));
+        }
+        Ok((checker, pattern))
     }
 
     /// Check if condition is true
@@ -26,21 +221,78 @@ impl ConditionChecker {
         match *self {
             ConditionChecker::Is => checker == to_check,
             ConditionChecker::Contains => to_check.contains(checker),
+            ConditionChecker::StartsWith => to_check.starts_with(checker),
+            ConditionChecker::EndsWith => to_check.ends_with(checker),
+            ConditionChecker::Matches(ref regex) => regex.is_match(to_check),
+            ConditionChecker::Glob(ref glob) => glob.matches(to_check),
+            // Pattern is folded too, it may have been changed since parsing, like flags
+            ConditionChecker::CaseInsensitive(ref inner) => {
+                inner.check(default_case_fold_str(checker), default_case_fold_str(to_check))
+            }
+            ConditionChecker::Not(ref inner) => !inner.check(checker, to_check),
+            ConditionChecker::Any(ref inner) |
+            ConditionChecker::Every(ref inner) => inner.check(checker, to_check),
+        }
+    }
+
+    /// Check if one address matches, by its name or by its address
+    fn check_address(&self, checker: &str, to_check: &MailAddress) -> bool {
+        match *self {
+            ConditionChecker::Not(ref inner) => !inner.check_address(checker, to_check),
+            _ => self.check(checker, &to_check.name) || self.check(checker, &to_check.address),
+        }
+    }
+
+    /// Check if condition is true, for a field with several values like a header
+    ///
+    /// Without explicit `any` or `every`, some value must match, or no value for negated checkers.
+    fn check_values<S: AsRef<str>>(&self, checker: S, to_checks: &[&str]) -> bool {
+        let checker = checker.as_ref();
+        match *self {
+            ConditionChecker::Any(ref inner) => to_checks.iter().any(|v| inner.check(checker, v)),
+            ConditionChecker::Every(ref inner) => to_checks.iter().all(|v| inner.check(checker, v)),
+            ConditionChecker::Not(ref inner) => !inner.check_values(checker, to_checks),
+            _ => to_checks.iter().any(|v| self.check(checker, v)),
         }
     }
 
     /// Check if condition is true, for mail address
+    ///
+    /// Without explicit `any` or `every`, some address must match,
+    /// or no address for negated checkers.
     fn check_mail<S: AsRef<str>>(&self, checker: S, to_checks: &Vec<MailAddress>) -> bool {
         let checker = checker.as_ref();
-        for to_check in to_checks {
-            if self.check(&checker, &to_check.name) || self.check(&checker, &to_check.address) {
-                return true;
+        match *self {
+            ConditionChecker::Any(ref inner) => {
+                to_checks.iter().any(|a| inner.check_address(checker, a))
             }
+            ConditionChecker::Every(ref inner) => {
+                to_checks.iter().all(|a| inner.check_address(checker, a))
+            }
+            ConditionChecker::Not(ref inner) => !inner.check_mail(checker, to_checks),
+            _ => to_checks.iter().any(|a| self.check_address(checker, a)),
         }
-        false
     }
 }
 
+/// Parse header name at start of text, quoted or not, and return it with the remaining text
+///
+/// Name must be usable in an imap fetch command, so it can't contain imap special characters.
+fn parse_header_name(text: &str) -> Option<(String, &str)> {
+    let (name, rest) = if let Some(quoted) = text.strip_prefix('"') {
+        let end = quoted.find('"')?;
+        (&quoted[..end], quoted[end + 1..].trim_start())
+    } else {
+        let name = text.split_whitespace().next().unwrap_or_default();
+        (name, strip_words(text, name).unwrap_or_default())
+    };
+    let valid = |c: char| c > ' ' && c <= '~' && !":(){%*\"\\]".contains(c);
+    if name.is_empty() || !name.chars().all(valid) {
+        return None;
+    }
+    Some((name.to_string(), rest))
+}
+
 #[derive(Debug, PartialEq)]
 enum ConditionType {
     Sender(ConditionChecker, String),
@@ -48,50 +300,228 @@ enum ConditionType {
     Recipient(ConditionChecker, String),
     Subject(ConditionChecker, String),
     Content(ConditionChecker, String),
+    /// Header name, checker and value
+    Header(String, ConditionChecker, String),
+    /// Condition on age or dates of mail
+    Date(DateCondition),
+    /// Size of whole mail in bytes
+    Size(Comparison, u64),
+    HasAttachment,
+    /// File names of attachments
+    AttachmentName(ConditionChecker, String),
+    /// Lowercase mime types of attachments, like `application/pdf`
+    AttachmentType(ConditionChecker, String),
+    AttachmentCount(Comparison, u64),
+    /// Flags and keywords of mail, like `\\Seen`
+    Flag(ConditionChecker, String),
+    /// Keywords of mail only, like `$Label1`
+    Keyword(ConditionChecker, String),
+    /// Folder where a mail of thread was moved or copied by an action, as written in the action
+    ThreadMovedTo(String),
+    /// Thread was muted by an action
+    ThreadMuted,
+    /// Id of mailing list, from List-Id header
+    List(ConditionChecker, String),
+    /// Mail comes from a mailing list
+    MailingList,
 }
 
 impl ConditionType {
     /// Parse string and return condition
     fn parse<S: AsRef<str>>(condition: S) -> Result<ConditionType> {
         let condition = condition.as_ref();
-        let splitted: Vec<&str> = condition.split_whitespace().collect();
-        let checker = ConditionChecker::parse(splitted[1])?;
-        let len = splitted[0].len() + splitted[1].len();
-        if splitted[0] == "sender" {
-            Ok(ConditionType::Sender(
-                checker,
-                condition[len + 2..].to_string(),
-            ))
-        } else if splitted[0] == "cc" {
-            Ok(ConditionType::Cc(checker, condition[len + 2..].to_string()))
-        } else if splitted[0] == "recipient" {
-            Ok(ConditionType::Recipient(
-                checker,
-                condition[len + 2..].to_string(),
-            ))
-        } else if splitted[0] == "subject" {
-            Ok(ConditionType::Subject(
-                checker,
-                condition[len + 2..].to_string(),
-            ))
-        } else if splitted[0] == "content" {
-            Ok(ConditionType::Content(
-                checker,
-                condition[len + 2..].to_string(),
-            ))
+        let (quantifier, text) = if let Some(rest) = strip_words(condition, "any") {
+            (Some("any"), rest)
+        } else if let Some(rest) = strip_words(condition, "every") {
+            (Some("every"), rest)
         } else {
+            (None, condition)
+        };
+        let field = text.split_whitespace().next().unwrap_or_default();
+        let mut rest = strip_words(text, field).unwrap_or_default();
+        if field == "age" || field == "date" || field == "received" {
+            return match (quantifier, DateCondition::parse(field, rest)) {
+                (None, Some(date)) => Ok(ConditionType::Date(date)),
+                _ => bail!(ErrorKind::InvalidCondition(condition.to_string())),
+            };
+        }
+        if field == "size" {
+            let size = Comparison::parse(rest)
+                .and_then(|(comparison, rest)| parse_size(rest).map(|s| (comparison, s)));
+            return match (quantifier, size) {
+                (None, Some((comparison, size))) => Ok(ConditionType::Size(comparison, size)),
+                _ => bail!(ErrorKind::InvalidCondition(condition.to_string())),
+            };
+        }
+        if field == "has" {
+            return match (quantifier, strip_words(rest, "attachment")) {
+                (None, Some("")) => Ok(ConditionType::HasAttachment),
+                _ => bail!(ErrorKind::InvalidCondition(condition.to_string())),
+            };
+        }
+        if field == "is" {
+            return match (quantifier, strip_words(rest, "mailing list")) {
+                (None, Some("")) => Ok(ConditionType::MailingList),
+                _ => bail!(ErrorKind::InvalidCondition(condition.to_string())),
+            };
+        }
+        if field == "thread" {
+            return match (quantifier, strip_words(rest, "moved to"), strip_words(rest, "is muted")) {
+                (None, Some(folder), _) if !folder.is_empty() => {
+                    Ok(ConditionType::ThreadMovedTo(folder.to_string()))
+                }
+                (None, _, Some("")) => Ok(ConditionType::ThreadMuted),
+                _ => bail!(ErrorKind::InvalidCondition(condition.to_string())),
+            };
+        }
+        if field == "attachment" {
+            if let (None, Some(rest)) = (quantifier, strip_words(rest, "count")) {
+                return match Comparison::parse(rest) {
+                    Some((comparison, count)) if count.parse::<u64>().is_ok() => {
+                        Ok(ConditionType::AttachmentCount(comparison, count.parse().unwrap()))
+                    }
+                    _ => bail!(ErrorKind::InvalidCondition(condition.to_string())),
+                };
+            }
+        }
+        let mut header = String::new();
+        if field == "header" {
+            match parse_header_name(rest) {
+                Some((name, after)) => {
+                    header = name;
+                    rest = after;
+                }
+                None => bail!(ErrorKind::InvalidCondition(condition.to_string())),
+            }
+        } else if field == "attachment" {
+            // Sub field is kept in header, `name` or `type`
+            let sub_field = rest.split_whitespace().next().unwrap_or_default();
+            match strip_words(rest, sub_field) {
+                Some(after) if sub_field == "name" || sub_field == "type" => {
+                    header = sub_field.to_string();
+                    rest = after;
+                }
+                _ => bail!(ErrorKind::InvalidCondition(condition.to_string())),
+            }
+        }
+        if rest.is_empty() {
             bail!(ErrorKind::InvalidCondition(condition.to_string()));
         }
+        let (mut checker, value) = match ConditionChecker::parse(rest) {
+            Ok(parsed) => parsed,
+            // `keyword $Label1` means that mail has this keyword
+            Err(_) if field == "keyword" && !rest.contains(char::is_whitespace) => {
+                (ConditionChecker::Is, rest.to_string())
+            }
+            Err(e) => return Err(e),
+        };
+        // Only address fields, headers and attachments have several values
+        match (quantifier, field) {
+            (None, _) => {}
+            (Some(quantifier), "sender") |
+            (Some(quantifier), "cc") |
+            (Some(quantifier), "recipient") |
+            (Some(quantifier), "header") |
+            (Some(quantifier), "attachment") |
+            (Some(quantifier), "flag") |
+            (Some(quantifier), "keyword") => {
+                checker = if quantifier == "any" {
+                    ConditionChecker::Any(Box::new(checker))
+                } else {
+                    ConditionChecker::Every(Box::new(checker))
+                };
+            }
+            _ => bail!(ErrorKind::InvalidCondition(condition.to_string())),
+        }
+        match field {
+            "sender" => Ok(ConditionType::Sender(checker, value)),
+            "cc" => Ok(ConditionType::Cc(checker, value)),
+            "recipient" => Ok(ConditionType::Recipient(checker, value)),
+            "subject" => Ok(ConditionType::Subject(checker, value)),
+            "content" => Ok(ConditionType::Content(checker, value)),
+            "header" => Ok(ConditionType::Header(header, checker, value)),
+            "attachment" if header == "name" => Ok(ConditionType::AttachmentName(checker, value)),
+            "attachment" => Ok(ConditionType::AttachmentType(checker, value)),
+            // Flags are case insensitive, mail flags are normalized the same way
+            "flag" => Ok(ConditionType::Flag(checker, normalize_flag(&value))),
+            "keyword" => Ok(ConditionType::Keyword(checker, value)),
+            "list" => Ok(ConditionType::List(checker, value)),
+            _ => bail!(ErrorKind::InvalidCondition(condition.to_string())),
+        }
     }
 
     /// Check if condition is true
-    fn check(&self, mail: &Mail) -> bool {
+    fn check(&self, mail: &Mail, context: &Context) -> bool {
         match self {
-            &ConditionType::Sender(ref c, ref checker) => c.check_mail(&checker, &mail.from),
-            &ConditionType::Cc(ref c, ref checker) => c.check_mail(&checker, &mail.cc),
-            &ConditionType::Recipient(ref c, ref checker) => c.check_mail(&checker, &mail.to),
-            &ConditionType::Subject(ref c, ref checker) => c.check(&checker, mail.subject.trim()),
-            &ConditionType::Content(ref c, ref checker) => c.check(&checker, mail.content.trim()),
+            ConditionType::Sender(c, checker) => c.check_mail(checker, &mail.from),
+            ConditionType::Cc(c, checker) => c.check_mail(checker, &mail.cc),
+            ConditionType::Recipient(c, checker) => c.check_mail(checker, &mail.to),
+            ConditionType::Subject(c, checker) => c.check(checker, mail.subject.trim()),
+            ConditionType::Content(c, checker) => c.check(checker, mail.content.trim()),
+            ConditionType::Header(name, c, checker) => {
+                let values: Vec<String> =
+                    mail.header_values(name).iter().map(|v| decode_words(v)).collect();
+                let values: Vec<&str> = values.iter().map(|v| v.as_str()).collect();
+                c.check_values(checker, &values)
+            }
+            ConditionType::Date(date) => date.check(mail, context),
+            ConditionType::Size(comparison, size) => {
+                match mail.size {
+                    Some(mail_size) => comparison.compare(&mail_size, size),
+                    None => false,
+                }
+            }
+            ConditionType::HasAttachment => {
+                mail.structure.as_ref().map(|s| !s.attachments().is_empty()) == Some(true)
+            }
+            ConditionType::AttachmentName(c, checker) => {
+                let names: Vec<String> = match mail.structure {
+                    Some(ref structure) => {
+                        structure.attachments().iter().filter_map(|a| a.name()).collect()
+                    }
+                    None => Vec::new(),
+                };
+                let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+                c.check_values(checker, &names)
+            }
+            ConditionType::AttachmentType(c, checker) => {
+                let types: Vec<&str> = match mail.structure {
+                    Some(ref structure) => {
+                        structure.attachments().iter().map(|a| a.mime_type.as_str()).collect()
+                    }
+                    None => Vec::new(),
+                };
+                c.check_values(checker, &types)
+            }
+            ConditionType::AttachmentCount(comparison, count) => {
+                match mail.structure {
+                    Some(ref structure) => {
+                        comparison.compare(&(structure.attachments().len() as u64), count)
+                    }
+                    None => false,
+                }
+            }
+            ConditionType::Flag(c, checker) => {
+                let flags: Vec<&str> = mail.flags.iter().map(|f| f.as_str()).collect();
+                c.check_values(checker, &flags)
+            }
+            ConditionType::Keyword(c, checker) => {
+                let keywords: Vec<&str> = mail.flags
+                    .iter()
+                    .filter(|f| !f.starts_with('\\'))
+                    .map(|f| f.as_str())
+                    .collect();
+                c.check_values(checker, &keywords)
+            }
+            ConditionType::ThreadMovedTo(folder) => {
+                mail.thread.folders.iter().any(|f| f == folder)
+            }
+            ConditionType::ThreadMuted => mail.thread.muted,
+            ConditionType::List(c, checker) => {
+                let ids: Vec<&str> = mail.list_id.iter().map(|id| id.as_str()).collect();
+                c.check_values(checker, &ids)
+            }
+            ConditionType::MailingList => mail.is_mailing_list(),
         }
     }
 }
@@ -106,9 +536,31 @@ impl Condition {
         Ok(Condition(cond))
     }
 
-    /// Check if mail respects condition
+    /// Check if mail respects condition, now and in local timezone
+    #[cfg(test)]
     pub fn check(&self, mail: &Mail) -> bool {
-        self.0.check(mail)
+        self.check_with(mail, &Context::default())
+    }
+
+    /// Check if mail respects condition in given context
+    pub fn check_with(&self, mail: &Mail, context: &Context) -> bool {
+        self.0.check(mail, context)
+    }
+
+    /// Name of header needed by condition, if it isn't one of the usual fields
+    pub fn header(&self) -> Option<&str> {
+        match self.0 {
+            ConditionType::Header(ref name, _, _) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Age compared by condition, the only condition whose result changes with time
+    pub fn age(&self) -> Option<Duration> {
+        match self.0 {
+            ConditionType::Date(DateCondition::Age(_, duration)) => Some(duration),
+            _ => None,
+        }
     }
 }
 
@@ -186,6 +638,444 @@ mod unit_tests {
         );
     }
 
+    #[test]
+    fn condition_matches() {
+        assert_eq!(
+            ConditionType::parse("subject matches ^\\[rust\\] .*$").unwrap(),
+            ConditionType::Subject(
+                ConditionChecker::Matches(Regex::new("^\\[rust\\] .*$").unwrap()),
+                "^\\[rust\\] .*$".to_string(),
+            ),
+            "fail with subject matches"
+        );
+        let err = ConditionType::parse("sender matches (unclosed").unwrap_err();
+        match *err.kind() {
+            ErrorKind::InvalidRegex(ref regex, _) => assert_eq!(regex, "(unclosed"),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn condition_check_matches() {
+        let mail = Mail::parse_fetched(
+            vec![
+                "Subject: [rust] Release 1.20\r\n",
+                "From: Inconnito <inconnito@superrito.com>\r\n",
+                "To: hineen1975@superrito.com\r\n",
+                "\r\n",
+                "Hello world\r\n",
+            ].iter()
+                .map(|s| s.to_string())
+                .collect(),
+        ).unwrap();
+        let c = Condition::new("subject matches ^\\[rust\\] Release \\d+\\.\\d+$").unwrap();
+        assert!(c.check(&mail));
+        let c = Condition::new("sender matches ^[a-z]+@superrito\\.com$").unwrap();
+        assert!(c.check(&mail));
+        let c = Condition::new("recipient matches ^[a-z]+@superrito\\.com$").unwrap();
+        assert!(!c.check(&mail));
+        let c = Condition::new("content matches (?m)^Hello").unwrap();
+        assert!(c.check(&mail));
+    }
+
+    #[test]
+    fn condition_multi_word_checkers() {
+        assert_eq!(
+            ConditionType::parse("sender ends with @gmail.com").unwrap(),
+            ConditionType::Sender(ConditionChecker::EndsWith, "@gmail.com".to_string())
+        );
+        assert_eq!(
+            ConditionType::parse("subject  starts   with [rust] ").unwrap(),
+            ConditionType::Subject(ConditionChecker::StartsWith, "[rust] ".to_string())
+        );
+        assert_eq!(
+            ConditionType::parse("subject contains (i) Invoice").unwrap(),
+            ConditionType::Subject(
+                ConditionChecker::CaseInsensitive(Box::new(ConditionChecker::Contains)),
+                "invoice".to_string(),
+            )
+        );
+        assert_eq!(
+            ConditionType::parse("subject is (invoice)").unwrap(),
+            ConditionType::Subject(ConditionChecker::Is, "(invoice)".to_string())
+        );
+        assert_eq!(
+            ConditionType::parse("sender glob *@*.example.com").unwrap(),
+            ConditionType::Sender(
+                ConditionChecker::Glob(Pattern::new("*@*.example.com").unwrap()),
+                "*@*.example.com".to_string(),
+            )
+        );
+        match *ConditionType::parse("subject starts hello").unwrap_err().kind() {
+            ErrorKind::InvalidConditionChecker(ref checker) => assert_eq!(checker, "starts"),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+        match *ConditionType::parse("subject glob [a-").unwrap_err().kind() {
+            ErrorKind::InvalidGlob(ref glob, _) => assert_eq!(glob, "[a-"),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+        assert!(ConditionType::parse("subject").is_err());
+        assert!(ConditionType::parse("").is_err());
+    }
+
+    #[test]
+    fn condition_check_string_checkers() {
+        let mail = Mail::parse_fetched(
+            vec![
+                "Subject: =?utf-8?Q?Facture_STRASSE_n=C2=B012?=\r\n",
+                "From: Billing <billing@notgmail.com.evil>\r\n",
+                "To: hineen1975@superrito.com\r\n",
+                "\r\n",
+                "Hello world\r\n",
+            ].iter()
+                .map(|s| s.to_string())
+                .collect(),
+        ).unwrap();
+        assert!(Condition::new("sender contains gmail.com").unwrap().check(&mail));
+        assert!(!Condition::new("sender ends with @gmail.com").unwrap().check(&mail));
+        assert!(Condition::new("sender starts with billing@").unwrap().check(&mail));
+        assert!(Condition::new("sender glob *@*.evil").unwrap().check(&mail));
+        assert!(!Condition::new("sender glob *@gmail.com").unwrap().check(&mail));
+        assert!(!Condition::new("subject starts with facture").unwrap().check(&mail));
+        assert!(Condition::new("subject starts with (i) facture").unwrap().check(&mail));
+        // Full case folding makes ß equal to SS
+        assert!(Condition::new("subject contains (i) straße").unwrap().check(&mail));
+        assert!(Condition::new("subject glob (i) facture*N°1?").unwrap().check(&mail));
+        assert!(Condition::new("subject matches (i) ^facture").unwrap().check(&mail));
+    }
+
+    #[test]
+    fn condition_negated() {
+        assert_eq!(
+            ConditionType::parse("subject is not hello").unwrap(),
+            ConditionType::Subject(
+                ConditionChecker::Not(Box::new(ConditionChecker::Is)),
+                "hello".to_string(),
+            )
+        );
+        assert_eq!(
+            ConditionType::parse("subject does not contain (i) Hello").unwrap(),
+            ConditionType::Subject(
+                ConditionChecker::Not(Box::new(ConditionChecker::CaseInsensitive(
+                    Box::new(ConditionChecker::Contains),
+                ))),
+                "hello".to_string(),
+            )
+        );
+        assert_eq!(
+            ConditionType::parse("sender not glob *@test.com").unwrap(),
+            ConditionType::Sender(
+                ConditionChecker::Not(Box::new(
+                    ConditionChecker::Glob(Pattern::new("*@test.com").unwrap()),
+                )),
+                "*@test.com".to_string(),
+            )
+        );
+        assert_eq!(
+            ConditionType::parse("every recipient does not end with @test.com").unwrap(),
+            ConditionType::Recipient(
+                ConditionChecker::Every(Box::new(
+                    ConditionChecker::Not(Box::new(ConditionChecker::EndsWith)),
+                )),
+                "@test.com".to_string(),
+            )
+        );
+        assert!(ConditionType::parse("any subject is hello").is_err());
+        assert!(ConditionType::parse("subject not hello").is_err());
+    }
+
+    #[test]
+    fn condition_check_negated() {
+        let mail = Mail::parse_fetched(
+            vec![
+                "Subject: Hehe\r\n",
+                "From: Inconnito <inconnito@superrito.com>\r\n",
+                "To: Boss <boss@corp.com>, friend@superrito.com\r\n",
+                "\r\n",
+                "Hello world\r\n",
+            ].iter()
+                .map(|s| s.to_string())
+                .collect(),
+        ).unwrap();
+        assert!(Condition::new("subject is not Hoho").unwrap().check(&mail));
+        assert!(!Condition::new("subject does not contain (i) HEHE").unwrap().check(&mail));
+        assert!(Condition::new("content does not match ^Bye").unwrap().check(&mail));
+        // By default, no address must match
+        assert!(!Condition::new("recipient is not boss@corp.com").unwrap().check(&mail));
+        assert!(Condition::new("recipient is not other@corp.com").unwrap().check(&mail));
+        // Name and address of the same mailbox must both not match
+        assert!(!Condition::new("any sender is not Inconnito").unwrap().check(&mail));
+        assert!(Condition::new("any recipient is not boss@corp.com").unwrap().check(&mail));
+        assert!(!Condition::new("every recipient ends with @corp.com").unwrap().check(&mail));
+        assert!(Condition::new("every recipient contains @").unwrap().check(&mail));
+        assert!(!Condition::new("every recipient does not end with .com").unwrap().check(&mail));
+    }
+
+    #[test]
+    fn condition_header() {
+        assert_eq!(
+            ConditionType::parse("header \"List-Id\" contains rust").unwrap(),
+            ConditionType::Header(
+                "List-Id".to_string(),
+                ConditionChecker::Contains,
+                "rust".to_string(),
+            )
+        );
+        assert_eq!(
+            ConditionType::parse("every header Received ends with .test.com").unwrap(),
+            ConditionType::Header(
+                "Received".to_string(),
+                ConditionChecker::Every(Box::new(ConditionChecker::EndsWith)),
+                ".test.com".to_string(),
+            )
+        );
+        assert_eq!(
+            Condition::new("header X-Spam-Flag is YES").unwrap().header(),
+            Some("X-Spam-Flag")
+        );
+        assert_eq!(Condition::new("subject is YES").unwrap().header(), None);
+        assert!(ConditionType::parse("header \"List-Id contains rust").is_err());
+        assert!(ConditionType::parse("header \"List Id\" contains rust").is_err());
+        assert!(ConditionType::parse("header List-Id)").is_err());
+        assert!(ConditionType::parse("header List-Id").is_err());
+    }
+
+    #[test]
+    fn condition_check_header() {
+        let mail = Mail::parse_fetched(
+            vec![
+                "Subject: Hehe\r\n",
+                "From: Inconnito <inconnito@superrito.com>\r\n",
+                "List-Id: Rust users <users.rust-lang.org>\r\n",
+                "Received: from a.test.com\r\n",
+                "Received: from b.other.com\r\n",
+                "\r\n",
+                "Hello world\r\n",
+            ].iter()
+                .map(|s| s.to_string())
+                .collect(),
+        ).unwrap();
+        let c = Condition::new("header list-id ends with <users.rust-lang.org>").unwrap();
+        assert!(c.check(&mail));
+        let c = Condition::new("header X-Spam-Flag is YES").unwrap();
+        assert!(!c.check(&mail));
+        let c = Condition::new("header X-Spam-Flag is not YES").unwrap();
+        assert!(c.check(&mail));
+        let c = Condition::new("header Received ends with .other.com").unwrap();
+        assert!(c.check(&mail));
+        let c = Condition::new("every header Received ends with .other.com").unwrap();
+        assert!(!c.check(&mail));
+        let c = Condition::new("header Received does not contain evil").unwrap();
+        assert!(c.check(&mail));
+    }
+
+    #[test]
+    fn condition_date() {
+        assert!(ConditionType::parse("age > 30d").is_ok());
+        assert!(ConditionType::parse("received time between 18:00 and 08:00").is_ok());
+        assert!(ConditionType::parse("date before 2026-01-01").is_ok());
+        assert!(ConditionType::parse("date is 2026-01-01").is_err());
+        assert!(ConditionType::parse("every date before 2026-01-01").is_err());
+        let mail = Mail::parse_fetched(vec![
+            "Subject: Hehe\r\nDate: Sat, 3 Jan 2026 12:00:00 +0000\r\n\r\n".to_string(),
+        ]).unwrap();
+        assert!(Condition::new("date before 2100-01-01").unwrap().check(&mail));
+        assert!(!Condition::new("age < 1h").unwrap().check(&mail));
+    }
+
+    #[test]
+    fn condition_size() {
+        assert_eq!(
+            ConditionType::parse("size > 10MB").unwrap(),
+            ConditionType::Size(Comparison::Greater, 10 * 1024 * 1024)
+        );
+        assert_eq!(
+            ConditionType::parse("size <= 2 kb").unwrap(),
+            ConditionType::Size(Comparison::LessOrEqual, 2048)
+        );
+        assert_eq!(
+            ConditionType::parse("size < 100").unwrap(),
+            ConditionType::Size(Comparison::Less, 100)
+        );
+        assert!(ConditionType::parse("size > 10TB").is_err());
+        assert!(ConditionType::parse("size 10MB").is_err());
+        assert!(ConditionType::parse("size is 10MB").is_err());
+        let mut mail = Mail::parse_fetched(vec!["Subject: Hehe\r\n\r\n".to_string()]).unwrap();
+        let c = Condition::new("size > 1KB").unwrap();
+        assert!(!c.check(&mail));
+        mail.size = Some(2048);
+        assert!(c.check(&mail));
+        assert!(!Condition::new("size < 2KB").unwrap().check(&mail));
+        assert!(Condition::new("size >= 2KB").unwrap().check(&mail));
+    }
+
+    #[test]
+    fn condition_attachment() {
+        assert_eq!(ConditionType::parse("has attachment").unwrap(), ConditionType::HasAttachment);
+        assert_eq!(
+            ConditionType::parse("attachment name matches \\.pdf$").unwrap(),
+            ConditionType::AttachmentName(
+                ConditionChecker::Matches(Regex::new("\\.pdf$").unwrap()),
+                "\\.pdf$".to_string(),
+            )
+        );
+        assert_eq!(
+            ConditionType::parse("every attachment type is image/png").unwrap(),
+            ConditionType::AttachmentType(
+                ConditionChecker::Every(Box::new(ConditionChecker::Is)),
+                "image/png".to_string(),
+            )
+        );
+        assert_eq!(
+            ConditionType::parse("attachment count > 3").unwrap(),
+            ConditionType::AttachmentCount(Comparison::Greater, 3)
+        );
+        assert!(ConditionType::parse("has attachments galore").is_err());
+        assert!(ConditionType::parse("attachment size > 3").is_err());
+        assert!(ConditionType::parse("attachment count > many").is_err());
+        assert!(ConditionType::parse("any attachment count > 3").is_err());
+    }
+
+    #[test]
+    fn condition_check_attachment() {
+        use mime::Part;
+        let part = |mime_type: &str, name: Option<&str>| {
+            Part {
+                mime_type: mime_type.to_string(),
+                disposition: Some("attachment".to_string()),
+                disposition_params: name.map(|n| ("filename".to_string(), n.to_string()))
+                    .into_iter()
+                    .collect(),
+                ..Part::default()
+            }
+        };
+        let mut mail = Mail::parse_fetched(vec!["Subject: Hehe\r\n\r\n".to_string()]).unwrap();
+        assert!(!Condition::new("has attachment").unwrap().check(&mail));
+        assert!(Condition::new("attachment name does not match \\.exe$").unwrap().check(&mail));
+        mail.structure = Some(Part {
+            mime_type: "multipart/mixed".to_string(),
+            parts: vec![
+                Part {
+                    mime_type: "text/plain".to_string(),
+                    ..Part::default()
+                },
+                part("application/pdf", Some("Report.PDF")),
+                part("application/zip", None),
+            ],
+            ..Part::default()
+        });
+        assert!(Condition::new("has attachment").unwrap().check(&mail));
+        assert!(Condition::new("attachment name matches (i) \\.pdf$").unwrap().check(&mail));
+        assert!(!Condition::new("attachment name matches \\.exe$").unwrap().check(&mail));
+        assert!(Condition::new("attachment type is application/zip").unwrap().check(&mail));
+        assert!(!Condition::new("every attachment type starts with image/").unwrap().check(&mail));
+        assert!(Condition::new("attachment count >= 2").unwrap().check(&mail));
+        assert!(!Condition::new("attachment count > 3").unwrap().check(&mail));
+    }
+
+    #[test]
+    fn condition_flag() {
+        assert_eq!(
+            ConditionType::parse("flag is \\seen").unwrap(),
+            ConditionType::Flag(ConditionChecker::Is, "\\Seen".to_string())
+        );
+        assert_eq!(
+            ConditionType::parse("flag is not \\Flagged").unwrap(),
+            ConditionType::Flag(
+                ConditionChecker::Not(Box::new(ConditionChecker::Is)),
+                "\\Flagged".to_string(),
+            )
+        );
+        assert_eq!(
+            ConditionType::parse("keyword $Label1").unwrap(),
+            ConditionType::Keyword(ConditionChecker::Is, "$Label1".to_string())
+        );
+        assert_eq!(
+            ConditionType::parse("keyword starts with $Label").unwrap(),
+            ConditionType::Keyword(ConditionChecker::StartsWith, "$Label".to_string())
+        );
+        assert!(ConditionType::parse("flag \\Seen").is_err());
+        assert!(ConditionType::parse("keyword").is_err());
+        assert!(ConditionType::parse("keyword two words").is_err());
+    }
+
+    #[test]
+    fn condition_check_flag() {
+        let mut mail = Mail::parse_fetched(vec!["Subject: Hehe\r\n\r\n".to_string()]).unwrap();
+        assert!(!Condition::new("flag is \\Seen").unwrap().check(&mail));
+        assert!(Condition::new("flag is not \\Seen").unwrap().check(&mail));
+        mail.add_flags("\\Seen $Label1");
+        assert!(Condition::new("flag is \\SEEN").unwrap().check(&mail));
+        assert!(Condition::new("flag is not \\Flagged").unwrap().check(&mail));
+        assert!(Condition::new("flag is $Label1").unwrap().check(&mail));
+        assert!(Condition::new("flag is (i) \\SEEN").unwrap().check(&mail));
+        assert!(Condition::new("flag is (i) \\seen").unwrap().check(&mail));
+        assert!(Condition::new("flag is (i) $label1").unwrap().check(&mail));
+        assert!(!Condition::new("flag is (i) \\flagged").unwrap().check(&mail));
+        assert!(Condition::new("keyword $Label1").unwrap().check(&mail));
+        assert!(!Condition::new("keyword $Label2").unwrap().check(&mail));
+        assert!(!Condition::new("keyword is \\Seen").unwrap().check(&mail));
+        assert!(Condition::new("every keyword starts with $").unwrap().check(&mail));
+        assert!(!Condition::new("every flag starts with $").unwrap().check(&mail));
+    }
+
+    #[test]
+    fn condition_thread() {
+        assert_eq!(
+            ConditionType::parse("thread moved to Project-X").unwrap(),
+            ConditionType::ThreadMovedTo("Project-X".to_string())
+        );
+        assert_eq!(
+            ConditionType::parse("thread  is   muted").unwrap(),
+            ConditionType::ThreadMuted
+        );
+        assert!(ConditionType::parse("thread moved to").is_err());
+        assert!(ConditionType::parse("thread is muted now").is_err());
+        assert!(ConditionType::parse("any thread is muted").is_err());
+    }
+
+    #[test]
+    fn condition_check_thread() {
+        let mut mail = Mail::parse_fetched(vec!["Subject: Hehe\r\n\r\n".to_string()]).unwrap();
+        assert!(!Condition::new("thread moved to Project-X").unwrap().check(&mail));
+        assert!(!Condition::new("thread is muted").unwrap().check(&mail));
+        mail.thread.folders.push("Project-X".to_string());
+        mail.thread.muted = true;
+        assert!(Condition::new("thread moved to Project-X").unwrap().check(&mail));
+        assert!(!Condition::new("thread moved to Project").unwrap().check(&mail));
+        assert!(Condition::new("thread is muted").unwrap().check(&mail));
+    }
+
+    #[test]
+    fn condition_list() {
+        assert_eq!(
+            ConditionType::parse("list is rust-users.lists.rust-lang.org").unwrap(),
+            ConditionType::List(ConditionChecker::Is, "rust-users.lists.rust-lang.org".to_string())
+        );
+        assert_eq!(ConditionType::parse("is mailing list").unwrap(), ConditionType::MailingList);
+        assert!(ConditionType::parse("is mailing").is_err());
+        assert!(ConditionType::parse("any list is rust").is_err());
+    }
+
+    #[test]
+    fn condition_check_list() {
+        let list = Mail::parse_fetched(vec![
+            "List-Id: Rust users <rust-users.lists.rust-lang.org>\r\n\r\n".to_string(),
+        ]).unwrap();
+        let other = Mail::parse_fetched(vec!["Subject: Hehe\r\n\r\n".to_string()]).unwrap();
+        let c = Condition::new("list is rust-users.lists.rust-lang.org").unwrap();
+        assert!(c.check(&list));
+        assert!(!c.check(&other));
+        let c = Condition::new("list starts with rust-users.").unwrap();
+        assert!(c.check(&list));
+        let c = Condition::new("list is not rust-users.lists.rust-lang.org").unwrap();
+        assert!(!c.check(&list));
+        assert!(c.check(&other));
+        let c = Condition::new("is mailing list").unwrap();
+        assert!(c.check(&list));
+        assert!(!c.check(&other));
+    }
+
     #[test]
     fn condition_check_subject() {
         let mail = Mail::parse_fetched(
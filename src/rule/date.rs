@@ -0,0 +1,290 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use mail::Mail;
+use rule::Context;
+use rule::condition::Comparison;
+
+/// Date of mail checked by condition
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DateField {
+    /// Date header, given by sender
+    Sent,
+    /// Date when server received the mail, Date header if server didn't give it
+    Received,
+}
+
+/// Condition on dates of a mail
+#[derive(Debug, PartialEq)]
+pub enum DateCondition {
+    /// Time since mail was received, like `age > 30d`
+    Age(Comparison, Duration),
+    /// Like `date before 2026-01-01`, in timezone of account
+    Before(DateField, NaiveDateTime),
+    /// Like `date after 2026-01-01 12:00`, the given time is included
+    After(DateField, NaiveDateTime),
+    /// Like `received weekday in sat,sun`
+    WeekdayIn(DateField, Vec<Weekday>),
+    /// Like `received time between 18:00 and 08:00`, start is included and end is excluded
+    TimeBetween(DateField, NaiveTime, NaiveTime),
+}
+
+/// Parse duration like `30d`, with unit `m` (minutes), `h`, `d` or `w`
+fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.split_whitespace().collect::<String>();
+    let idx = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let count = match text[..idx].parse::<i64>() {
+        Ok(count) => count,
+        Err(_) => return None,
+    };
+    match &text[idx..] {
+        "m" => Some(Duration::minutes(count)),
+        "h" => Some(Duration::hours(count)),
+        "d" => Some(Duration::days(count)),
+        "w" => Some(Duration::weeks(count)),
+        _ => None,
+    }
+}
+
+/// Parse date like `2026-01-01` or `2026-01-01 12:00`
+fn parse_date(text: &str) -> Option<NaiveDateTime> {
+    let text = text.trim();
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").ok().or_else(|| {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").ok().and_then(|d| d.and_hms_opt(0, 0, 0))
+    })
+}
+
+/// Parse day like `sat` or `saturday`, case insensitive
+fn parse_weekday(text: &str) -> Option<Weekday> {
+    let days = [
+        ("mon", "monday", Weekday::Mon),
+        ("tue", "tuesday", Weekday::Tue),
+        ("wed", "wednesday", Weekday::Wed),
+        ("thu", "thursday", Weekday::Thu),
+        ("fri", "friday", Weekday::Fri),
+        ("sat", "saturday", Weekday::Sat),
+        ("sun", "sunday", Weekday::Sun),
+    ];
+    let text = text.to_lowercase();
+    days.iter()
+        .find(|&&(short, long, _)| text == short || text == long)
+        .map(|&(_, _, day)| day)
+}
+
+/// Parse time like `18:00`
+fn parse_time(text: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(text.trim(), "%H:%M").ok()
+}
+
+impl DateCondition {
+    /// Parse condition on field `age`, `date` or `received`, text is what follows field
+    pub fn parse(field: &str, text: &str) -> Option<DateCondition> {
+        let text = text.trim();
+        let field = match field {
+            "age" => {
+                return Comparison::parse(text).and_then(|(comparison, rest)| {
+                    parse_duration(rest).map(|d| DateCondition::Age(comparison, d))
+                });
+            }
+            "date" => DateField::Sent,
+            "received" => DateField::Received,
+            _ => return None,
+        };
+        let mut words = text.splitn(2, char::is_whitespace);
+        let (check, rest) = (words.next().unwrap_or_default(), words.next().unwrap_or_default());
+        match check {
+            "before" => parse_date(rest).map(|d| DateCondition::Before(field, d)),
+            "after" => parse_date(rest).map(|d| DateCondition::After(field, d)),
+            "weekday" => {
+                let rest = rest.trim_start();
+                if !rest.starts_with("in ") {
+                    return None;
+                }
+                let mut days = Vec::new();
+                for day in rest[3..].split(|c: char| c == ',' || c.is_whitespace()) {
+                    if day.is_empty() {
+                        continue;
+                    }
+                    match parse_weekday(day) {
+                        Some(day) => days.push(day),
+                        None => return None,
+                    }
+                }
+                if days.is_empty() {
+                    return None;
+                }
+                Some(DateCondition::WeekdayIn(field, days))
+            }
+            "time" => {
+                let rest = rest.trim_start();
+                if !rest.starts_with("between ") {
+                    return None;
+                }
+                let mut times = rest[8..].splitn(2, " and ");
+                let start = parse_time(times.next().unwrap_or_default());
+                let end = parse_time(times.next().unwrap_or_default());
+                match (start, end) {
+                    (Some(start), Some(end)) => Some(DateCondition::TimeBetween(field, start, end)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Check if mail respects condition, false when mail has no date
+    pub fn check(&self, mail: &Mail, context: &Context) -> bool {
+        let field = match *self {
+            DateCondition::Age(..) => DateField::Received,
+            DateCondition::Before(field, _) |
+            DateCondition::After(field, _) |
+            DateCondition::WeekdayIn(field, _) |
+            DateCondition::TimeBetween(field, _, _) => field,
+        };
+        let date = match field {
+            DateField::Sent => mail.date,
+            DateField::Received => mail.received.or(mail.date),
+        };
+        let date = match date {
+            Some(date) => date,
+            None => return false,
+        };
+        let local = context.timezone.naive_local(&date);
+        match *self {
+            DateCondition::Age(comparison, ref duration) => {
+                comparison.compare(&context.now.signed_duration_since(date), duration)
+            }
+            DateCondition::Before(_, ref limit) => {
+                match context.timezone.from_local(limit) {
+                    Some(limit) => date < limit,
+                    None => false,
+                }
+            }
+            DateCondition::After(_, ref limit) => {
+                match context.timezone.from_local(limit) {
+                    Some(limit) => date >= limit,
+                    None => false,
+                }
+            }
+            DateCondition::WeekdayIn(_, ref days) => days.contains(&local.weekday()),
+            DateCondition::TimeBetween(_, start, end) => {
+                let time = local.time();
+                if start <= end {
+                    start <= time && time < end
+                } else {
+                    // Range goes over midnight
+                    time >= start || time < end
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use timezone::Timezone;
+
+    fn mail(date: &str) -> Mail {
+        Mail::parse_fetched(vec![format!("Subject: Hehe\r\nDate: {}\r\n\r\n", date)]).unwrap()
+    }
+
+    fn context() -> Context {
+        Context {
+            now: Utc.ymd(2026, 1, 10).and_hms(12, 0, 0),
+            timezone: Timezone::parse("Europe/Paris").unwrap(),
+        }
+    }
+
+    #[test]
+    fn date_parse() {
+        assert_eq!(
+            DateCondition::parse("age", "> 30d"),
+            Some(DateCondition::Age(Comparison::Greater, Duration::days(30)))
+        );
+        assert_eq!(
+            DateCondition::parse("age", "<=2 h"),
+            Some(DateCondition::Age(Comparison::LessOrEqual, Duration::hours(2)))
+        );
+        assert_eq!(
+            DateCondition::parse("date", "before 2026-01-01"),
+            Some(DateCondition::Before(
+                DateField::Sent,
+                NaiveDate::from_ymd(2026, 1, 1).and_hms(0, 0, 0),
+            ))
+        );
+        assert_eq!(
+            DateCondition::parse("received", "after 2026-01-01 12:30"),
+            Some(DateCondition::After(
+                DateField::Received,
+                NaiveDate::from_ymd(2026, 1, 1).and_hms(12, 30, 0),
+            ))
+        );
+        assert_eq!(
+            DateCondition::parse("received", "weekday in sat, Sunday"),
+            Some(DateCondition::WeekdayIn(
+                DateField::Received,
+                vec![Weekday::Sat, Weekday::Sun],
+            ))
+        );
+        assert_eq!(
+            DateCondition::parse("received", "time between 18:00 and 08:00"),
+            Some(DateCondition::TimeBetween(
+                DateField::Received,
+                NaiveTime::from_hms(18, 0, 0),
+                NaiveTime::from_hms(8, 0, 0),
+            ))
+        );
+        for &(field, text) in &[
+            ("age", "30d"),
+            ("age", "> 30y"),
+            ("date", "before tomorrow"),
+            ("date", "weekday in someday"),
+            ("date", "weekday in"),
+            ("received", "time between 18:00"),
+            ("received", "time 18:00 and 08:00"),
+            ("subject", "before 2026-01-01"),
+        ]
+        {
+            assert_eq!(DateCondition::parse(field, text), None, "{} {}", field, text);
+        }
+    }
+
+    #[test]
+    fn date_check_age() {
+        let mail = mail("Sat, 3 Jan 2026 12:00:00 +0000");
+        let older = |d| DateCondition::Age(Comparison::Greater, Duration::days(d));
+        assert!(older(6).check(&mail, &context()));
+        assert!(!older(7).check(&mail, &context()));
+        assert!(DateCondition::Age(Comparison::GreaterOrEqual, Duration::days(7))
+            .check(&mail, &context()));
+        assert!(!older(0).check(&Mail::parse_fetched(vec!["Subject: Hehe\r\n\r\n".to_string()])
+            .unwrap(), &context()));
+    }
+
+    #[test]
+    fn date_check_before_after() {
+        // Midnight in Paris is 23:00 in UTC
+        let mail = mail("Wed, 31 Dec 2025 23:30:00 +0000");
+        let before = DateCondition::parse("date", "before 2026-01-01").unwrap();
+        let after = DateCondition::parse("date", "after 2026-01-01").unwrap();
+        assert!(!before.check(&mail, &context()));
+        assert!(after.check(&mail, &context()));
+    }
+
+    #[test]
+    fn date_check_received() {
+        let mut mail = mail("Sat, 10 Jan 2026 10:00:00 +0000");
+        let weekend = DateCondition::parse("received", "weekday in sat,sun").unwrap();
+        let night = DateCondition::parse("received", "time between 18:00 and 08:00").unwrap();
+        assert!(weekend.check(&mail, &context()));
+        assert!(!night.check(&mail, &context()));
+        // Server date is used rather than Date header, 23:30 in Paris on monday
+        mail.received = Some(
+            ::chrono::DateTime::parse_from_rfc2822("Mon, 12 Jan 2026 22:30:00 +0000").unwrap(),
+        );
+        assert!(!weekend.check(&mail, &context()));
+        assert!(night.check(&mail, &context()));
+        let day = DateCondition::parse("received", "time between 08:00 and 18:00").unwrap();
+        assert!(!day.check(&mail, &context()));
+    }
+}
@@ -0,0 +1,479 @@
+use chrono::Duration;
+use error::*;
+use mail::Mail;
+use rule::Context;
+use rule::condition::Condition;
+use toml::Value;
+
+/// Boolean expression made of conditions
+#[derive(Debug, PartialEq)]
+pub enum Expr {
+    Condition(Condition),
+    /// True when every expression is true, or when there is none
+    All(Vec<Expr>),
+    /// True when at least one expression is true
+    Any(Vec<Expr>),
+    Not(Box<Expr>),
+}
+
+impl Expr {
+    /// Parse expression string, like `(sender is a@b.c and not subject contains x) or cc is d@e.f`
+    ///
+    /// `not` binds tighter than `and`, which binds tighter than `or`. A condition ends before
+    /// an `and` or `or` word, or before `)` inside parentheses, quote it to use them in its value.
+    pub fn parse<S: AsRef<str>>(expr: S) -> Result<Expr> {
+        let expr = expr.as_ref();
+        let mut parser = Parser {
+            text: expr,
+            pos: 0,
+            depth: 0,
+        };
+        let result = parser.or()?;
+        parser.skip_spaces();
+        if parser.pos < expr.len() {
+            return Err(parser.error("unexpected `)`"));
+        }
+        Ok(result)
+    }
+
+    /// Convert toml value into expression
+    ///
+    /// A string is parsed as an expression, a table has one key: `all` or `any` with an array,
+    /// or `not` with one item. Toml arrays can't mix strings and tables, so strings inside
+    /// tables are expressions too.
+    pub fn from_toml(toml: &Value) -> Result<Expr> {
+        let table = match *toml {
+            Value::String(ref expr) => return Expr::parse(expr),
+            Value::Table(ref table) => table,
+            _ => bail!(ErrorKind::InvalidExpression(
+                toml.to_string(),
+                "expected a string or a table".to_string(),
+            )),
+        };
+        if table.len() != 1 {
+            bail!(ErrorKind::InvalidExpression(
+                toml.to_string(),
+                "a table must have only one of `all`, `any` or `not`".to_string(),
+            ));
+        }
+        let (key, value) = table.iter().next().unwrap();
+        match (key.as_str(), value) {
+            ("all", &Value::Array(ref items)) |
+            ("any", &Value::Array(ref items)) => {
+                if items.is_empty() {
+                    bail!(ErrorKind::InvalidExpression(
+                        toml.to_string(),
+                        format!("`{}` needs at least one item", key),
+                    ));
+                }
+                let mut exprs = Vec::new();
+                for item in items {
+                    exprs.push(Expr::from_toml(item)?);
+                }
+                if key == "all" {
+                    Ok(Expr::All(exprs))
+                } else {
+                    Ok(Expr::Any(exprs))
+                }
+            }
+            ("not", item) => Ok(Expr::Not(Box::new(Expr::from_toml(item)?))),
+            _ => bail!(ErrorKind::InvalidExpression(
+                toml.to_string(),
+                format!("unexpected `{}`", key),
+            )),
+        }
+    }
+
+    /// Names of headers needed by conditions of expression, besides the usual fields
+    pub fn headers(&self) -> Vec<&str> {
+        match *self {
+            Expr::Condition(ref condition) => condition.header().into_iter().collect(),
+            Expr::All(ref exprs) |
+            Expr::Any(ref exprs) => exprs.iter().flat_map(|e| e.headers()).collect(),
+            Expr::Not(ref expr) => expr.headers(),
+        }
+    }
+
+    /// Ages compared by conditions of expression, see `Condition::age`
+    pub fn ages(&self) -> Vec<Duration> {
+        match *self {
+            Expr::Condition(ref condition) => condition.age().into_iter().collect(),
+            Expr::All(ref exprs) |
+            Expr::Any(ref exprs) => exprs.iter().flat_map(|e| e.ages()).collect(),
+            Expr::Not(ref expr) => expr.ages(),
+        }
+    }
+
+    /// Check if mail respects expression, now and in local timezone
+    #[cfg(test)]
+    pub fn check(&self, mail: &Mail) -> bool {
+        self.check_with(mail, &Context::default())
+    }
+
+    /// Check if mail respects expression in given context
+    pub fn check_with(&self, mail: &Mail, context: &Context) -> bool {
+        match *self {
+            Expr::Condition(ref condition) => condition.check_with(mail, context),
+            Expr::All(ref exprs) => exprs.iter().all(|e| e.check_with(mail, context)),
+            Expr::Any(ref exprs) => exprs.iter().any(|e| e.check_with(mail, context)),
+            Expr::Not(ref expr) => !expr.check_with(mail, context),
+        }
+    }
+}
+
+/// Recursive descent parser of expression strings
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    /// Number of open parentheses
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn error(&self, reason: &str) -> Error {
+        ErrorKind::InvalidExpression(
+            self.text.to_string(),
+            format!("{} at position {}", reason, self.pos),
+        ).into()
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consume keyword if it is the next word
+    fn keyword(&mut self, keyword: &str) -> bool {
+        self.skip_spaces();
+        let rest = self.rest();
+        if !rest.starts_with(keyword) {
+            return false;
+        }
+        match rest[keyword.len()..].chars().next() {
+            None | Some('(') => {}
+            Some(c) if c.is_whitespace() => {}
+            Some(_) => return false,
+        }
+        self.pos += keyword.len();
+        true
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut exprs = vec![self.and()?];
+        while self.keyword("or") {
+            exprs.push(self.and()?);
+        }
+        if exprs.len() == 1 {
+            Ok(exprs.pop().unwrap())
+        } else {
+            Ok(Expr::Any(exprs))
+        }
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut exprs = vec![self.not()?];
+        while self.keyword("and") {
+            exprs.push(self.not()?);
+        }
+        if exprs.len() == 1 {
+            Ok(exprs.pop().unwrap())
+        } else {
+            Ok(Expr::All(exprs))
+        }
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        match self.rest().chars().next() {
+            Some('(') => {
+                self.pos += 1;
+                self.depth += 1;
+                let expr = self.or()?;
+                self.skip_spaces();
+                if !self.rest().starts_with(')') {
+                    return Err(self.error("missing `)`"));
+                }
+                self.pos += 1;
+                self.depth -= 1;
+                Ok(expr)
+            }
+            Some('"') => self.quoted_condition(),
+            _ => self.condition(),
+        }
+    }
+
+    /// Read condition between double quotes, `\"` and `\\` are unescaped
+    fn quoted_condition(&mut self) -> Result<Expr> {
+        let mut condition = String::new();
+        let mut chars = self.rest().char_indices().skip(1);
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += idx + 1;
+                    return Ok(Expr::Condition(Condition::new(condition)?));
+                }
+                '\\' => {
+                    if let Some((_, c)) = chars.next() {
+                        condition.push(c);
+                    }
+                }
+                c => condition.push(c),
+            }
+        }
+        Err(self.error("missing closing quote"))
+    }
+
+    /// Read condition until next `and` or `or` word, or `)` inside parentheses
+    ///
+    /// `and` is kept in conditions like `received time between 18:00 and 08:00`.
+    fn condition(&mut self) -> Result<Expr> {
+        let rest = self.rest();
+        let mut end = rest.len();
+        let mut in_word = false;
+        // Two last words, to find `between <time> and`
+        let mut previous = ("", "");
+        for (idx, c) in rest.char_indices() {
+            if self.depth > 0 && c == ')' {
+                end = idx;
+                break;
+            }
+            if c.is_whitespace() {
+                in_word = false;
+                continue;
+            }
+            if !in_word {
+                in_word = true;
+                let word = rest[idx..]
+                    .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                    .next()
+                    .unwrap_or_default();
+                if (word == "and" && previous.0 != "between") || word == "or" {
+                    end = idx;
+                    break;
+                }
+                previous = (previous.1, word);
+            }
+        }
+        let condition = rest[..end].trim();
+        if condition.is_empty() {
+            return Err(self.error("missing condition"));
+        }
+        self.pos += end;
+        Ok(Expr::Condition(Condition::new(condition)?))
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn cond(condition: &str) -> Expr {
+        Expr::Condition(Condition::new(condition).unwrap())
+    }
+
+    fn mail() -> Mail {
+        Mail::parse_fetched(vec![
+            "Subject: [ext] Meeting\r\n\
+             From: Inconnito <inconnito@corp.com>\r\n\
+             To: hineen1975@superrito.com\r\n\
+             Cc: boss@corp.com\r\n\
+             \r\n\
+             Hello world\r\n"
+                .to_string(),
+        ]).unwrap()
+    }
+
+    #[test]
+    fn expr_parse_precedence() {
+        assert_eq!(
+            Expr::parse("sender is a and subject is b or cc is c").unwrap(),
+            Expr::Any(vec![
+                Expr::All(vec![cond("sender is a"), cond("subject is b")]),
+                cond("cc is c"),
+            ])
+        );
+        assert_eq!(
+            Expr::parse("not sender is a and not not subject is b").unwrap(),
+            Expr::All(vec![
+                Expr::Not(Box::new(cond("sender is a"))),
+                Expr::Not(Box::new(Expr::Not(Box::new(cond("subject is b"))))),
+            ])
+        );
+    }
+
+    #[test]
+    fn expr_parse_parentheses() {
+        assert_eq!(
+            Expr::parse(
+                "(sender ends with @corp.com and not subject contains [ext]) or cc is boss@corp.com",
+            ).unwrap(),
+            Expr::Any(vec![
+                Expr::All(vec![
+                    cond("sender ends with @corp.com"),
+                    Expr::Not(Box::new(cond("subject contains [ext]"))),
+                ]),
+                cond("cc is boss@corp.com"),
+            ])
+        );
+        assert_eq!(
+            Expr::parse("not(subject is a or (subject is b))").unwrap(),
+            Expr::Not(Box::new(
+                Expr::Any(vec![cond("subject is a"), cond("subject is b")]),
+            ))
+        );
+        // Parentheses are only special inside a group
+        assert_eq!(
+            Expr::parse("subject is :)").unwrap(),
+            cond("subject is :)")
+        );
+    }
+
+    #[test]
+    fn expr_parse_between() {
+        assert_eq!(
+            Expr::parse("received time between 18:00 and 08:00 and subject is a").unwrap(),
+            Expr::All(vec![
+                cond("received time between 18:00 and 08:00"),
+                cond("subject is a"),
+            ])
+        );
+    }
+
+    #[test]
+    fn expr_parse_quoted() {
+        assert_eq!(
+            Expr::parse("\"subject is Tom and Jerry\" or (\"subject is :)\")").unwrap(),
+            Expr::Any(vec![cond("subject is Tom and Jerry"), cond("subject is :)")])
+        );
+        assert_eq!(
+            Expr::parse("\"subject is \\\"quoted\\\"\"").unwrap(),
+            cond("subject is \"quoted\"")
+        );
+    }
+
+    #[test]
+    fn expr_parse_invalid() {
+        for expr in &[
+            "",
+            "sender is a and",
+            "(sender is a",
+            "not",
+            "\"sender is a",
+            "sender is a or or cc is b",
+        ]
+        {
+            match Expr::parse(expr).map_err(|e| e.kind().to_string()) {
+                Err(ref e) if e.starts_with("expression") => {}
+                r => panic!("unexpected result for `{}`: {:?}", expr, r),
+            }
+        }
+        assert!(Expr::parse("sender is a and planet is b").is_err());
+    }
+
+    #[test]
+    fn expr_from_toml() {
+        let toml = ::toml::from_str::<Value>(
+            "match = { any = [ { all = [ \"sender ends with @corp.com\", \"cc is a or cc is b\" ] }, \
+             { not = { any = [ \"subject contains x\" ] } } ] }",
+        ).unwrap();
+        assert_eq!(
+            Expr::from_toml(&toml["match"]).unwrap(),
+            Expr::Any(vec![
+                Expr::All(vec![
+                    cond("sender ends with @corp.com"),
+                    Expr::Any(vec![cond("cc is a"), cond("cc is b")]),
+                ]),
+                Expr::Not(Box::new(Expr::Any(vec![cond("subject contains x")]))),
+            ])
+        );
+        let toml = ::toml::from_str::<Value>("match = \"sender is a or cc is b\"").unwrap();
+        assert_eq!(
+            Expr::from_toml(&toml["match"]).unwrap(),
+            Expr::Any(vec![cond("sender is a"), cond("cc is b")])
+        );
+        for invalid in &[
+            "match = 1",
+            "match = { all = \"sender is a\" }",
+            "match = { all = [], any = [] }",
+            "match = { none = [] }",
+            "match = { all = [] }",
+            "match = { any = [ { any = [] } ] }",
+        ]
+        {
+            let toml = ::toml::from_str::<Value>(invalid).unwrap();
+            assert!(Expr::from_toml(&toml["match"]).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn expr_headers() {
+        let expr = Expr::parse(
+            "header List-Id contains rust or not (subject is a and header X-Spam is YES)",
+        ).unwrap();
+        assert_eq!(expr.headers(), vec!["List-Id", "X-Spam"]);
+    }
+
+    #[test]
+    fn expr_ages() {
+        let expr = Expr::parse("age > 30d or not (flag is \\Seen and age <= 2h)").unwrap();
+        assert_eq!(expr.ages(), vec![Duration::days(30), Duration::hours(2)]);
+        assert!(Expr::parse("date before 2026-01-01").unwrap().ages().is_empty());
+    }
+
+    #[test]
+    fn expr_check_all() {
+        let mail = mail();
+        assert!(Expr::All(vec![]).check(&mail));
+        assert!(
+            Expr::parse("sender ends with @corp.com and subject contains Meeting")
+                .unwrap()
+                .check(&mail)
+        );
+        assert!(
+            !Expr::parse("sender ends with @corp.com and subject contains Party")
+                .unwrap()
+                .check(&mail)
+        );
+    }
+
+    #[test]
+    fn expr_check_any() {
+        let mail = mail();
+        assert!(!Expr::Any(vec![]).check(&mail));
+        assert!(
+            Expr::parse("subject contains Party or cc is boss@corp.com")
+                .unwrap()
+                .check(&mail)
+        );
+        assert!(
+            !Expr::parse("subject contains Party or cc is other@corp.com")
+                .unwrap()
+                .check(&mail)
+        );
+    }
+
+    #[test]
+    fn expr_check_not() {
+        let mail = mail();
+        assert!(Expr::parse("not subject contains Party").unwrap().check(&mail));
+        assert!(!Expr::parse("not subject contains Meeting").unwrap().check(&mail));
+        assert!(
+            Expr::parse(
+                "(sender ends with @corp.com and not subject contains [ext]) or cc is boss@corp.com",
+            ).unwrap()
+                .check(&mail)
+        );
+        assert!(
+            !Expr::parse(
+                "(sender ends with @corp.com and not subject contains [ext]) or cc is nobody@corp.com",
+            ).unwrap()
+                .check(&mail)
+        );
+    }
+}
@@ -1,19 +1,58 @@
 mod action;
 mod condition;
+mod date;
+mod expr;
 
 pub use self::action::*;
 pub use self::condition::*;
+pub use self::expr::*;
 
+use chrono::{DateTime, Duration, Utc};
 use error::*;
+use folder;
+use mail::Mail;
+use response::ListEntry;
+use timezone::Timezone;
 use toml::Value;
 
+/// What conditions need to know besides the mail
+#[derive(Clone, Debug)]
+pub struct Context {
+    pub now: DateTime<Utc>,
+    /// Timezone of dates and times given in conditions
+    pub timezone: Timezone,
+}
+
+impl Context {
+    /// Create context for current time
+    pub fn new(timezone: Timezone) -> Context {
+        Context::at(Utc::now(), timezone)
+    }
+
+    /// Create context for given time
+    pub fn at(now: DateTime<Utc>, timezone: Timezone) -> Context {
+        Context { now, timezone }
+    }
+}
+
+impl Default for Context {
+    fn default() -> Context {
+        Context::new(Timezone::default())
+    }
+}
+
 /// Data in toml for one rule
 #[derive(Deserialize)]
 struct RuleData {
     pub description: Option<String>,
     pub any: Option<bool>,
+    pub mailboxes: Option<Vec<String>>,
+    #[serde(rename = "match")]
+    pub expression: Option<Value>,
+    #[serde(default)]
     pub conditions: Vec<String>,
     pub actions: Vec<String>,
+    #[serde(default)]
     pub exceptions: Vec<String>,
 }
 
@@ -21,36 +60,176 @@ struct RuleData {
 #[derive(Debug)]
 pub struct Rule {
     pub name: String,
+    /// Only documents rule in config file
+    #[allow(dead_code)]
     pub description: Option<String>,
-    pub any: bool,
-    pub conditions: Vec<Condition>,
+    /// Patterns of mailboxes whose mails are checked, only `INBOX` by default even when the
+    /// account watches other mailboxes
+    pub mailboxes: Vec<String>,
+    /// `match` expression, conditions and exceptions all together
+    pub expression: Expr,
     pub actions: Vec<Action>,
-    pub exceptions: Vec<Condition>,
 }
 
 impl Rule {
     /// Convert toml table into Rule
     pub fn from_toml(name: String, toml: &Value) -> Result<Rule> {
         let data: RuleData = toml.clone().try_into::<RuleData>()?;
+        let mut exprs = Vec::new();
+        // `match = "all"` is the only way to apply a rule to every mail, a forgotten condition
+        // mustn't do it
+        let match_all = data.expression.as_ref().and_then(Value::as_str).map(str::trim) ==
+            Some("all");
+        if let Some(ref expression) = data.expression {
+            if !match_all {
+                exprs.push(Expr::from_toml(expression)?);
+            }
+        }
         let mut conditions = Vec::new();
         for condition in data.conditions {
-            conditions.push(Condition::new(condition)?);
+            conditions.push(Expr::Condition(Condition::new(condition)?));
         }
-        let mut actions = Vec::new();
-        for action in data.actions {
-            actions.push(Action::new(action)?);
+        if !conditions.is_empty() {
+            if data.any.unwrap_or(false) {
+                exprs.push(Expr::Any(conditions));
+            } else {
+                exprs.push(Expr::All(conditions));
+            }
         }
         let mut exceptions = Vec::new();
         for exception in data.exceptions {
-            exceptions.push(Condition::new(exception)?);
+            exceptions.push(Expr::Condition(Condition::new(exception)?));
+        }
+        if !exceptions.is_empty() {
+            exprs.push(Expr::Not(Box::new(Expr::Any(exceptions))));
+        }
+        if exprs.is_empty() && !match_all {
+            bail!(ErrorKind::MissingConditions);
+        }
+        let mut actions = Vec::new();
+        for action in data.actions {
+            actions.push(Action::new(action)?);
         }
         Ok(Rule {
-            name: name,
+            name,
             description: data.description,
-            any: data.any.unwrap_or(false),
-            conditions: conditions,
-            actions: actions,
-            exceptions: exceptions,
+            mailboxes: data.mailboxes.unwrap_or_else(|| vec!["INBOX".to_string()]),
+            expression: Expr::All(exprs),
+            actions,
+        })
+    }
+
+    /// Check if mail meets rule conditions
+    pub fn matches(&self, mail: &Mail, context: &Context) -> bool {
+        self.expression.check_with(mail, context)
+    }
+
+    /// Check if mail meets rule conditions now but didn't at a previous check
+    ///
+    /// Both checks use current flags of mail, so a rule on age and flags like
+    /// `age > 7d and flag is not \Seen` starts matching once, when mail gets too old unread.
+    pub fn starts_matching(&self, mail: &Mail, previous: &Context, context: &Context) -> bool {
+        self.matches(mail, context) && !self.matches(mail, previous)
+    }
+
+    /// Ages compared by conditions of rule, rules without them never change their mind on a mail
+    pub fn ages(&self) -> Vec<Duration> {
+        self.expression.ages()
+    }
+
+    /// Check if rule must be applied to mails of this mailbox
+    pub fn applies_to(&self, mailbox: &ListEntry) -> bool {
+        self.mailboxes.iter().any(|pattern| {
+            folder::matches(
+                pattern,
+                &mailbox.name,
+                mailbox.delimiter.as_deref(),
+            )
         })
     }
 }
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn rule_conditions_and_exceptions() {
+        let toml = ::toml::from_str::<Value>(
+            "any = true\n\
+             conditions = [ \"sender is a@test.com\", \"cc is b@test.com\" ]\n\
+             exceptions = [ \"subject contains spam\" ]\n\
+             actions = [ \"mark as read\" ]",
+        ).unwrap();
+        let rule = Rule::from_toml("rule".to_string(), &toml).unwrap();
+        assert_eq!(
+            rule.expression,
+            Expr::All(vec![
+                Expr::Any(vec![
+                    Expr::Condition(Condition::new("sender is a@test.com").unwrap()),
+                    Expr::Condition(Condition::new("cc is b@test.com").unwrap()),
+                ]),
+                Expr::Not(Box::new(Expr::Any(vec![
+                    Expr::Condition(Condition::new("subject contains spam").unwrap()),
+                ]))),
+            ])
+        );
+        let toml = ::toml::from_str::<Value>(
+            "match = \"sender is a@test.com or cc is b@test.com\"\n\
+             actions = [ \"mark as read\" ]",
+        ).unwrap();
+        let rule = Rule::from_toml("rule".to_string(), &toml).unwrap();
+        assert_eq!(
+            rule.expression,
+            Expr::All(vec![
+                Expr::Any(vec![
+                    Expr::Condition(Condition::new("sender is a@test.com").unwrap()),
+                    Expr::Condition(Condition::new("cc is b@test.com").unwrap()),
+                ]),
+            ])
+        );
+    }
+
+    #[test]
+    fn rule_without_conditions() {
+        let rule = |toml: &str| {
+            Rule::from_toml("rule".to_string(), &::toml::from_str(toml).unwrap())
+        };
+        match *rule("actions = [ \"mark as read\" ]").unwrap_err().kind() {
+            ErrorKind::MissingConditions => {}
+            ref e => panic!("unexpected error {}", e),
+        }
+        assert!(rule("conditions = []\nexceptions = []\nactions = [ \"delete\" ]").is_err());
+        let all = rule("match = \"all\"\nactions = [ \"mark as read\" ]").unwrap();
+        let mail = Mail::parse_fetched(vec!["Subject: spam\r\n\r\n".to_string()]).unwrap();
+        assert!(all.matches(&mail, &Context::default()));
+        let except = rule(
+            "match = \"all\"\n\
+             exceptions = [ \"subject contains spam\" ]\n\
+             actions = [ \"mark as read\" ]",
+        ).unwrap();
+        assert!(!except.matches(&mail, &Context::default()));
+    }
+
+    #[test]
+    fn rule_starts_matching() {
+        let toml = ::toml::from_str::<Value>(
+            "conditions = [ \"age > 7d\", \"flag is not \\\\Seen\" ]\n\
+             actions = [ \"move to Later\" ]",
+        ).unwrap();
+        let rule = Rule::from_toml("unread".to_string(), &toml).unwrap();
+        assert_eq!(rule.ages(), vec![Duration::days(7)]);
+        let mut mail = Mail::parse_fetched(vec![
+            "Subject: Hehe\r\nDate: Sat, 3 Jan 2026 12:00:00 +0000\r\n\r\n".to_string(),
+        ]).unwrap();
+        let at = |day| {
+            Context::at(Utc.with_ymd_and_hms(2026, 1, day, 12, 30, 0).unwrap(), Timezone::Local)
+        };
+        assert!(rule.starts_matching(&mail, &at(9), &at(10)));
+        assert!(!rule.starts_matching(&mail, &at(8), &at(9)));
+        assert!(!rule.starts_matching(&mail, &at(10), &at(11)));
+        mail.add_flags("\\Seen");
+        assert!(!rule.starts_matching(&mail, &at(9), &at(10)));
+    }
+}
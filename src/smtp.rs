@@ -0,0 +1,354 @@
+use error::*;
+use openssl::ssl::{SslConnectorBuilder, SslMethod, SslStream};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use toml::Value;
+
+/// Seconds to wait for the smtp server before giving up
+const SMTP_TIMEOUT: u64 = 60;
+
+/// How the connection to the smtp server is secured
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    None,
+    Starttls,
+    Tls,
+}
+
+/// Smtp server used to send mails
+#[derive(Debug, Deserialize)]
+pub struct Smtp {
+    pub host: String,
+    pub port: u16,
+    pub tls: TlsMode,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: Option<String>,
+}
+
+impl Smtp {
+    /// Convert toml table into Smtp
+    pub fn from_toml(toml: &Value) -> Result<Smtp> {
+        toml.clone().try_into().map_err(|e| e.into())
+    }
+
+    /// Send a mail to all recipients
+    pub fn send(&self, from: &str, to: &[&str], message: &str) -> Result<()> {
+        let mut client = SmtpClient::connect(self)?;
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            client.auth(username, password)?;
+        }
+        let mut mail_from = format!("MAIL FROM:<{}>", from);
+        if !message.is_ascii() {
+            // 8bit content can only be sent to servers supporting it (RFC 6152)
+            if !client.has_extension("8BITMIME") {
+                bail!(ErrorKind::SmtpUnsupported("8BITMIME".to_string()));
+            }
+            mail_from.push_str(" BODY=8BITMIME");
+        }
+        client.command(&mail_from, 250)?;
+        for rcpt in to {
+            client.command(&format!("RCPT TO:<{}>", rcpt), 250)?;
+        }
+        client.command("DATA", 354)?;
+        client.data(message)?;
+        client.command("QUIT", 221)?;
+        Ok(())
+    }
+}
+
+enum Stream {
+    Plain(TcpStream),
+    Secure(SslStream<TcpStream>),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Plain(ref mut s) => s.read(buf),
+            Stream::Secure(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Plain(ref mut s) => s.write(buf),
+            Stream::Secure(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Plain(ref mut s) => s.flush(),
+            Stream::Secure(ref mut s) => s.flush(),
+        }
+    }
+}
+
+/// Connection to a smtp server
+struct SmtpClient {
+    stream: BufReader<Stream>,
+    extensions: Vec<String>,
+}
+
+impl SmtpClient {
+    /// Connect to server, say hello and secure connection if needed
+    fn connect(smtp: &Smtp) -> Result<SmtpClient> {
+        let tcp = TcpStream::connect((smtp.host.as_str(), smtp.port)).map_err(connection_error)?;
+        let timeout = Some(Duration::from_secs(SMTP_TIMEOUT));
+        tcp.set_read_timeout(timeout).map_err(connection_error)?;
+        tcp.set_write_timeout(timeout).map_err(connection_error)?;
+        let stream = match smtp.tls {
+            TlsMode::Tls => Stream::Secure(secure(&smtp.host, tcp)?),
+            _ => Stream::Plain(tcp),
+        };
+        let mut client = SmtpClient {
+            stream: BufReader::new(stream),
+            extensions: Vec::new(),
+        };
+        client.read_reply(220)?;
+        client.ehlo()?;
+        if smtp.tls == TlsMode::Starttls {
+            client.command("STARTTLS", 220)?;
+            let tcp = match client.stream.into_inner() {
+                Stream::Plain(tcp) => tcp,
+                Stream::Secure(_) => unreachable!(),
+            };
+            client = SmtpClient {
+                stream: BufReader::new(Stream::Secure(secure(&smtp.host, tcp)?)),
+                extensions: Vec::new(),
+            };
+            client.ehlo()?;
+        }
+        Ok(client)
+    }
+
+    fn ehlo(&mut self) -> Result<()> {
+        let lines = self.command("EHLO narricky", 250)?;
+        self.extensions = lines.iter().skip(1).map(|l| l.to_uppercase()).collect();
+        Ok(())
+    }
+
+    /// Check if server advertised extension, like `8BITMIME`
+    fn has_extension(&self, extension: &str) -> bool {
+        self.extensions.iter().any(
+            |e| e.split_whitespace().next() == Some(extension),
+        )
+    }
+
+    /// Authenticate with PLAIN mechanism, or LOGIN if PLAIN is not supported
+    fn auth(&mut self, username: &str, password: &str) -> Result<()> {
+        let login = self.extensions.iter().any(|e| {
+            e.starts_with("AUTH") && e.contains("LOGIN") && !e.contains("PLAIN")
+        });
+        if login {
+            self.command("AUTH LOGIN", 334)?;
+            self.command(&::base64::encode(username.as_bytes()), 334)?;
+            self.command(&::base64::encode(password.as_bytes()), 235)?;
+        } else {
+            let token = format!("\0{}\0{}", username, password);
+            self.command(
+                &format!("AUTH PLAIN {}", ::base64::encode(token.as_bytes())),
+                235,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Send a command and check reply code, reply lines are returned
+    fn command(&mut self, command: &str, code: u16) -> Result<Vec<String>> {
+        write!(self.stream.get_mut(), "{}\r\n", command).map_err(connection_error)?;
+        self.stream.get_mut().flush().map_err(connection_error)?;
+        self.read_reply(code)
+    }
+
+    /// Send mail content, lines starting with a dot are escaped
+    fn data(&mut self, message: &str) -> Result<()> {
+        write_data(self.stream.get_mut(), message).map_err(connection_error)?;
+        self.read_reply(250)?;
+        Ok(())
+    }
+
+    /// Read a reply which can be on multiple lines, like `250-first` `250 last`
+    fn read_reply(&mut self, code: u16) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).map_err(connection_error)? == 0 {
+                bail!(ErrorKind::Smtp("connection closed by server".to_string()));
+            }
+            let line = line.trim_end().to_string();
+            let last = line.as_bytes().get(3) != Some(&b'-');
+            let reply_code = line.get(..3).and_then(|c| c.parse::<u16>().ok());
+            lines.push(line.get(4..).unwrap_or_default().to_string());
+            if last {
+                if reply_code != Some(code) {
+                    bail!(ErrorKind::Smtp(line));
+                }
+                return Ok(lines);
+            }
+        }
+    }
+}
+
+/// Write mail content ended by a dot, lines starting with a dot are escaped
+fn write_data<W: Write>(stream: &mut W, message: &str) -> io::Result<()> {
+    for line in message.lines() {
+        if line.starts_with('.') {
+            stream.write_all(b".")?;
+        }
+        stream.write_all(line.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+    }
+    stream.write_all(b".\r\n")?;
+    stream.flush()
+}
+
+/// Error of the connection to the smtp server
+///
+/// It has its own kind, so it isn't taken for a lost imap connection.
+fn connection_error<E: ::std::fmt::Display>(error: E) -> Error {
+    ErrorKind::SmtpConnection(error.to_string()).into()
+}
+
+/// Wrap stream into a tls session
+fn secure(host: &str, tcp: TcpStream) -> Result<SslStream<TcpStream>> {
+    let connector = SslConnectorBuilder::new(SslMethod::tls())
+        .map_err(connection_error)?
+        .build();
+    connector.connect(host, tcp).map_err(connection_error)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Fake smtp server accepting one mail, returns received commands and data
+    fn stand_in(extensions: &'static [u8]) -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut received = Vec::new();
+            writer.write_all(b"220 localhost ready\r\n").unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_right_matches("\r\n").to_string();
+                received.push(line.clone());
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    extensions
+                } else if line.starts_with("AUTH") {
+                    b"235 ok\r\n"
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go on\r\n"
+                } else if line == "QUIT" {
+                    writer.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                writer.write_all(reply).unwrap();
+            }
+            received
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn smtp_send() {
+        let (port, handle) = stand_in(b"250-localhost\r\n250 AUTH PLAIN\r\n");
+        let smtp = Smtp {
+            host: "127.0.0.1".to_string(),
+            port: port,
+            tls: TlsMode::None,
+            username: Some("user".to_string()),
+            password: Some("pass".to_string()),
+            from: None,
+        };
+        smtp.send(
+            "me@test.com",
+            &["you@test.com"],
+            "Subject: Hi\r\n\r\nHello\r\n.hidden dot\r\n",
+        ).unwrap();
+        let received = handle.join().unwrap();
+        assert_eq!(
+            received,
+            vec![
+                "EHLO narricky",
+                "AUTH PLAIN AHVzZXIAcGFzcw==",
+                "MAIL FROM:<me@test.com>",
+                "RCPT TO:<you@test.com>",
+                "DATA",
+                "Subject: Hi",
+                "",
+                "Hello",
+                "..hidden dot",
+                ".",
+                "QUIT",
+            ]
+        );
+    }
+
+    fn smtp(port: u16) -> Smtp {
+        Smtp {
+            host: "127.0.0.1".to_string(),
+            port,
+            tls: TlsMode::None,
+            username: None,
+            password: None,
+            from: None,
+        }
+    }
+
+    #[test]
+    fn smtp_send_8bit() {
+        let (port, handle) = stand_in(b"250-localhost\r\n250 8BITMIME\r\n");
+        smtp(port).send("me@test.com", &["you@test.com"], "Subject: Hi\r\n\r\nCafé\r\n").unwrap();
+        let received = handle.join().unwrap();
+        assert_eq!(received[1], "MAIL FROM:<me@test.com> BODY=8BITMIME");
+        let (port, handle) = stand_in(b"250 localhost\r\n");
+        let error = smtp(port)
+            .send("me@test.com", &["you@test.com"], "Subject: Hi\r\n\r\nCafé\r\n")
+            .unwrap_err();
+        assert!(matches!(*error.kind(), ErrorKind::SmtpUnsupported(_)));
+        assert_eq!(handle.join().unwrap(), vec!["EHLO narricky"]);
+    }
+
+    #[test]
+    fn smtp_connection_error() {
+        // Port of a closed listener refuses connections
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let error = smtp(port).send("me@test.com", &["you@test.com"], "Hello\r\n").unwrap_err();
+        assert!(matches!(*error.kind(), ErrorKind::SmtpConnection(_)));
+        // Imap connection is still fine, the mail is skipped rather than retried
+        assert!(!error.is_connection());
+    }
+
+    #[test]
+    fn smtp_tls_mode() {
+        let smtp = Smtp::from_toml(&::toml::from_str::<Value>(
+            "host = \"smtp.test.com\"\nport = 587\ntls = \"starttls\"",
+        ).unwrap()).unwrap();
+        assert_eq!(smtp.tls, TlsMode::Starttls);
+        assert_eq!(smtp.username, None);
+    }
+}
@@ -0,0 +1,252 @@
+use account::Account;
+use error::*;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use threading::Thread;
+
+/// Seconds without mail nor action on a thread before it is forgotten, 90 days
+const THREAD_LIFETIME: i64 = 90 * 24 * 3600;
+
+/// Processing state of one mailbox
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct MailboxState {
+    pub uid_validity: u32,
+    pub last_uid: u32,
+    /// Time when rules on age were last checked on processed mails, 0 if they never were
+    #[serde(default)]
+    pub last_rescan: i64,
+    /// Time when new mails were last listed, 0 if they never were
+    #[serde(default)]
+    pub last_check: i64,
+}
+
+/// State of an account, kept on disk between runs
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct State {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    mailboxes: BTreeMap<String, MailboxState>,
+    /// Addresses which received an automatic reply, with time of the reply
+    #[serde(default)]
+    replies: BTreeMap<String, i64>,
+    /// Threads rules acted on, by thread key
+    #[serde(default)]
+    threads: BTreeMap<String, Thread>,
+    /// Key of thread of each message id in threads
+    #[serde(skip)]
+    thread_keys: BTreeMap<String, String>,
+}
+
+impl State {
+    /// Directory where states are stored, `$XDG_DATA_HOME/narricky` by default
+    pub fn data_dir() -> PathBuf {
+        match env::var_os("XDG_DATA_HOME") {
+            Some(ref dir) if !dir.is_empty() => PathBuf::from(dir).join("narricky"),
+            _ => {
+                env::home_dir()
+                    .unwrap_or_else(env::temp_dir)
+                    .join(".local/share/narricky")
+            }
+        }
+    }
+
+    /// Path of the state file for this account
+    pub fn path_for(account: &Account) -> PathBuf {
+        let name = format!("{}@{}", account.username, account.domain).replace('/', "_");
+        State::data_dir().join(format!("{}.toml", name))
+    }
+
+    /// Read state from file, an empty state is returned if file doesn't exist
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<State> {
+        let path = path.as_ref();
+        let mut state = if path.exists() {
+            let mut file = File::open(path)?;
+            let mut buf = String::new();
+            file.read_to_string(&mut buf)?;
+            ::toml::from_str::<State>(&buf)?
+        } else {
+            State::default()
+        };
+        state.path = path.to_path_buf();
+        for (key, thread) in &state.threads {
+            for id in &thread.ids {
+                state.thread_keys.insert(id.clone(), key.clone());
+            }
+        }
+        Ok(state)
+    }
+
+    /// Write state to its file
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let buf = ::toml::to_string(self)?;
+        // Write to a temporary file first so a crash never leaves a truncated state
+        let tmp = self.path.with_extension("toml.tmp");
+        File::create(&tmp)?.write_all(buf.as_bytes())?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Get state of a mailbox
+    pub fn mailbox(&mut self, mailbox_name: &str) -> &mut MailboxState {
+        self.mailboxes
+            .entry(mailbox_name.to_string())
+            .or_default()
+    }
+
+    /// Check if an automatic reply was sent to address less than `interval` seconds ago
+    pub fn replied_recently(&self, address: &str, interval: i64, now: i64) -> bool {
+        match self.replies.get(&address.to_lowercase()) {
+            Some(time) => now - *time < interval,
+            None => false,
+        }
+    }
+
+    /// Remember that an automatic reply was sent to address, replies older than `interval` are forgotten
+    pub fn record_reply(&mut self, address: &str, interval: i64, now: i64) {
+        self.replies = self.replies
+            .iter()
+            .filter(|&(_, time)| now - *time < interval)
+            .map(|(address, time)| (address.clone(), *time))
+            .collect();
+        self.replies.insert(address.to_lowercase(), now);
+    }
+
+    /// Find key of a remembered thread containing one of the message ids
+    pub fn find_thread(&self, ids: &[&str]) -> Option<String> {
+        ids.iter().filter_map(|id| self.thread_keys.get(*id)).next().cloned()
+    }
+
+    /// Get remembered thread
+    pub fn thread(&self, key: &str) -> Option<&Thread> {
+        self.threads.get(key)
+    }
+
+    /// Remember thread, replacing previous one with the same key
+    pub fn save_thread(&mut self, key: &str, thread: Thread) {
+        for id in &thread.ids {
+            self.thread_keys.insert(id.clone(), key.to_string());
+        }
+        self.threads.insert(key.to_string(), thread);
+    }
+
+    /// Forget threads without activity for `THREAD_LIFETIME`
+    pub fn prune_threads(&mut self, now: i64) {
+        // Threads saved before activity was recorded start their lifetime now
+        for thread in self.threads.values_mut() {
+            if thread.last_activity == 0 {
+                thread.last_activity = now;
+            }
+        }
+        let expired: Vec<String> = self.threads
+            .iter()
+            .filter(|&(_, thread)| now - thread.last_activity >= THREAD_LIFETIME)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            if let Some(thread) = self.threads.remove(&key) {
+                for id in thread.ids {
+                    if self.thread_keys.get(&id) == Some(&key) {
+                        self.thread_keys.remove(&id);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn state_missing_file() {
+        let path = env::temp_dir().join("narricky-state-missing.toml");
+        let _ = fs::remove_file(&path);
+        let mut state = State::load(&path).unwrap();
+        assert_eq!(state.mailbox("INBOX"), &mut MailboxState::default());
+    }
+
+    #[test]
+    fn state_save_load() {
+        let path = env::temp_dir().join("narricky-state-save-load.toml");
+        let mut state = State::load(&path).unwrap();
+        *state.mailbox("INBOX") = MailboxState {
+            uid_validity: 12,
+            last_uid: 345,
+            last_rescan: 1767225600,
+            last_check: 1767229200,
+        };
+        state.mailbox("Lists/rust").last_uid = 6;
+        state.save().unwrap();
+        let mut state = State::load(&path).unwrap();
+        assert_eq!(state.mailbox("INBOX").uid_validity, 12);
+        assert_eq!(state.mailbox("INBOX").last_uid, 345);
+        assert_eq!(state.mailbox("INBOX").last_rescan, 1767225600);
+        assert_eq!(state.mailbox("INBOX").last_check, 1767229200);
+        assert_eq!(state.mailbox("Lists/rust").last_uid, 6);
+        state.save_thread("a@x.com", Thread {
+            ids: vec!["a@x.com".to_string(), "b@x.com".to_string()],
+            folders: vec!["Project-X".to_string()],
+            muted: true,
+            last_activity: 1767225600,
+        });
+        state.save().unwrap();
+        let state = State::load(&path).unwrap();
+        assert_eq!(state.thread("a@x.com").unwrap().folders, vec!["Project-X"]);
+        assert!(state.thread("a@x.com").unwrap().muted);
+        assert_eq!(state.thread("a@x.com").unwrap().last_activity, 1767225600);
+        assert_eq!(state.find_thread(&["b@x.com"]), Some("a@x.com".to_string()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn state_replies() {
+        let mut state = State::default();
+        state.record_reply("Hello@World.com", 100, 1000);
+        assert!(state.replied_recently("hello@world.com", 100, 1050));
+        assert!(!state.replied_recently("hello@world.com", 100, 1100));
+        assert!(!state.replied_recently("other@world.com", 100, 1050));
+        state.record_reply("other@world.com", 100, 1200);
+        assert_eq!(state.replies.len(), 1);
+    }
+
+    #[test]
+    fn state_threads() {
+        let mut state = State::default();
+        assert_eq!(state.find_thread(&["a@x.com"]), None);
+        let mut thread = Thread::default();
+        thread.add_ids(&["a@x.com", "b@x.com", "a@x.com"]);
+        assert_eq!(thread.ids, vec!["a@x.com", "b@x.com"]);
+        state.save_thread("a@x.com", thread);
+        assert_eq!(state.find_thread(&["c@x.com", "b@x.com"]), Some("a@x.com".to_string()));
+        assert_eq!(state.find_thread(&["c@x.com"]), None);
+        assert_eq!(state.thread("b@x.com"), None);
+    }
+
+    #[test]
+    fn state_prune_threads() {
+        let mut state = State::default();
+        let thread = |id: &str, last_activity| {
+            Thread {
+                ids: vec![id.to_string()],
+                last_activity,
+                ..Thread::default()
+            }
+        };
+        state.save_thread("old", thread("old@x.com", 1000));
+        state.save_thread("new", thread("new@x.com", 1000 + THREAD_LIFETIME));
+        state.save_thread("legacy", thread("legacy@x.com", 0));
+        state.prune_threads(1000 + THREAD_LIFETIME);
+        assert_eq!(state.thread("old"), None);
+        assert_eq!(state.find_thread(&["old@x.com"]), None);
+        assert_eq!(state.find_thread(&["new@x.com"]), Some("new".to_string()));
+        assert_eq!(state.thread("legacy").unwrap().last_activity, 1000 + THREAD_LIFETIME);
+    }
+}
@@ -0,0 +1,292 @@
+use chrono::Utc;
+use mail::Mail;
+use state::State;
+use std::collections::HashMap;
+
+/// Thread of mails remembered in state, because rules acted on it
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Thread {
+    /// Message ids of known mails of thread
+    #[serde(default)]
+    pub ids: Vec<String>,
+    /// Folders where rules moved or copied mails of thread, as written in actions
+    #[serde(default)]
+    pub folders: Vec<String>,
+    /// Set by `mute thread` action
+    #[serde(default)]
+    pub muted: bool,
+    /// Time of last mail or action on thread, threads inactive for long are forgotten
+    #[serde(default)]
+    pub last_activity: i64,
+}
+
+impl Thread {
+    /// Add message ids to known mails of thread
+    pub fn add_ids(&mut self, ids: &[&str]) {
+        for id in ids {
+            if !self.ids.iter().any(|i| i == id) {
+                self.ids.push(id.to_string());
+            }
+        }
+    }
+}
+
+/// Containers of JWZ algorithm, for mails and for ids only known from references
+struct Containers {
+    /// Container of each known id
+    ids: HashMap<String, usize>,
+    /// Parent of each container
+    parents: Vec<Option<usize>>,
+}
+
+impl Containers {
+    /// Get container of id, it is created if id is unknown
+    fn get(&mut self, id: &str) -> usize {
+        if let Some(&container) = self.ids.get(id) {
+            return container;
+        }
+        let container = self.create();
+        self.ids.insert(id.to_string(), container);
+        container
+    }
+
+    /// Create a container without id
+    fn create(&mut self) -> usize {
+        self.parents.push(None);
+        self.parents.len() - 1
+    }
+
+    /// Check if `ancestor` is `container` or one of its ancestors
+    fn is_ancestor(&self, ancestor: usize, container: usize) -> bool {
+        let mut current = Some(container);
+        while let Some(c) = current {
+            if c == ancestor {
+                return true;
+            }
+            current = self.parents[c];
+        }
+        false
+    }
+
+    /// Set parent of container, unless it would make a loop
+    fn link(&mut self, parent: usize, child: usize) {
+        if !self.is_ancestor(child, parent) {
+            self.parents[child] = Some(parent);
+        }
+    }
+
+    /// Top ancestor of container
+    fn root(&self, container: usize) -> usize {
+        let mut root = container;
+        while let Some(parent) = self.parents[root] {
+            root = parent;
+        }
+        root
+    }
+}
+
+/// Group mails in threads with references, like JWZ algorithm (https://www.jwz.org/doc/threading.html)
+///
+/// Subjects aren't used, mails without id nor references are alone in their thread. Indexes of
+/// mails of each thread are returned, threads are sorted by their first mail.
+pub fn thread_mails(mails: &[Mail]) -> Vec<Vec<usize>> {
+    let mut containers = Containers {
+        ids: HashMap::new(),
+        parents: Vec::new(),
+    };
+    let mut used = Vec::new();
+    let mut mail_containers = Vec::new();
+    for mail in mails {
+        // Mails with the same id are kept apart, like a mail without id
+        let container = match mail.message_id {
+            Some(ref id) => {
+                let container = containers.get(id);
+                if used.contains(&container) {
+                    containers.create()
+                } else {
+                    container
+                }
+            }
+            None => containers.create(),
+        };
+        used.push(container);
+        mail_containers.push(container);
+        // References give the chain of parents, In-Reply-To is used only without references
+        let references: Vec<&str> = if mail.references.is_empty() {
+            mail.in_reply_to.iter().take(1).map(|id| id.as_str()).collect()
+        } else {
+            mail.references.iter().map(|id| id.as_str()).collect()
+        };
+        let chain: Vec<usize> = references.iter().map(|id| containers.get(id)).collect();
+        for pair in chain.windows(2) {
+            if containers.parents[pair[1]].is_none() && pair[0] != pair[1] {
+                containers.link(pair[0], pair[1]);
+            }
+        }
+        match chain.last() {
+            Some(&parent) if parent != container => containers.link(parent, container),
+            _ => {}
+        }
+    }
+    let mut threads: Vec<(usize, Vec<usize>)> = Vec::new();
+    for (idx, &container) in mail_containers.iter().enumerate() {
+        let root = containers.root(container);
+        match threads.iter().position(|&(r, _)| r == root) {
+            Some(position) => threads[position].1.push(idx),
+            None => threads.push((root, vec![idx])),
+        }
+    }
+    threads.into_iter().map(|(_, mails)| mails).collect()
+}
+
+/// Convert threads of uids given by server to threads of mail indexes
+///
+/// Mails missing from threads are alone in their thread.
+pub fn uid_threads(mails: &[Mail], threads: &[Vec<u32>]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = threads
+        .iter()
+        .map(|uids| {
+            uids.iter()
+                .filter_map(|uid| mails.iter().position(|m| m.uid == *uid))
+                .collect::<Vec<usize>>()
+        })
+        .filter(|group| !group.is_empty())
+        .collect();
+    for idx in 0..mails.len() {
+        if !groups.iter().any(|group| group.contains(&idx)) {
+            groups.push(vec![idx]);
+        }
+    }
+    for group in &mut groups {
+        group.sort();
+    }
+    groups.sort_by_key(|group| group[0]);
+    groups
+}
+
+/// Set thread key of mails, grouped in threads
+///
+/// A thread remembered in state with one of the ids of the group keeps its key, otherwise the key
+/// is the id of the first mail of thread found in references of the oldest mail.
+pub fn identify(mails: &mut [Mail], threads: &[Vec<usize>], state: &State) {
+    for thread in threads {
+        let key = {
+            let ids: Vec<&str> = thread.iter().flat_map(|&idx| mails[idx].thread_ids()).collect();
+            match state.find_thread(&ids) {
+                Some(key) => Some(key),
+                None => ids.first().map(|id| id.to_string()),
+            }
+        };
+        for &idx in thread {
+            mails[idx].thread_id = key.clone();
+        }
+    }
+}
+
+/// Read thread of mail from state, mail joins thread if it is remembered
+pub fn load(mail: &mut Mail, state: &mut State) {
+    let key = match mail.thread_id {
+        Some(ref key) => key.clone(),
+        None => return,
+    };
+    if let Some(thread) = state.thread(&key) {
+        mail.thread = thread.clone();
+    }
+    if !mail.thread.ids.is_empty() {
+        let mut thread = mail.thread.clone();
+        thread.add_ids(&mail.thread_ids());
+        thread.last_activity = Utc::now().timestamp();
+        state.save_thread(&key, thread.clone());
+        mail.thread = thread;
+    }
+}
+
+/// Change thread of mail and remember it in state
+///
+/// Return false if mail has no thread, because it has no message id nor references.
+pub fn update<F: FnOnce(&mut Thread)>(mail: &mut Mail, state: &mut State, change: F) -> bool {
+    let key = match mail.thread_id {
+        Some(ref key) => key.clone(),
+        None => return false,
+    };
+    let mut thread = mail.thread.clone();
+    thread.add_ids(&mail.thread_ids());
+    thread.last_activity = Utc::now().timestamp();
+    change(&mut thread);
+    state.save_thread(&key, thread.clone());
+    mail.thread = thread;
+    true
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn mail(uid: u32, headers: &str) -> Mail {
+        let mut mail = Mail::parse_fetched(vec![format!("{}\r\n\r\n", headers)]).unwrap();
+        mail.uid = uid;
+        mail
+    }
+
+    #[test]
+    fn thread_jwz() {
+        let mails = vec![
+            mail(1, "Message-ID: <a@x>"),
+            mail(2, "Message-ID: <other@x>"),
+            // Parent is missing, thread is joined by references
+            mail(3, "Message-ID: <c@x>\r\nReferences: <a@x> <b@x>"),
+            mail(4, "Message-ID: <d@x>\r\nIn-Reply-To: <b@x>"),
+            mail(5, "Subject: no id"),
+            mail(6, "Message-ID: <e@x>\r\nIn-Reply-To: <other@x>\r\nReferences: <other@x>"),
+            // Loop in references is ignored
+            mail(7, "Message-ID: <f@x>\r\nReferences: <f@x> <c@x>"),
+        ];
+        assert_eq!(thread_mails(&mails), vec![vec![0, 2, 3, 6], vec![1, 5], vec![4]]);
+    }
+
+    #[test]
+    fn thread_uid_threads() {
+        let mails: Vec<Mail> = (1..5).map(|uid| mail(uid, "Subject: Hehe")).collect();
+        assert_eq!(
+            uid_threads(&mails, &[vec![4, 2], vec![9], vec![1]]),
+            vec![vec![0], vec![1, 3], vec![2]]
+        );
+    }
+
+    #[test]
+    fn thread_identify() {
+        let mut mails = vec![
+            mail(1, "Message-ID: <b@x>\r\nReferences: <a@x>"),
+            mail(2, "Message-ID: <c@x>\r\nReferences: <a@x> <b@x>"),
+            mail(3, "Message-ID: <z@x>\r\nReferences: <y@x>"),
+            mail(4, "Subject: no id"),
+        ];
+        let mut state = State::default();
+        state.save_thread("known", Thread {
+            ids: vec!["y@x".to_string()],
+            ..Thread::default()
+        });
+        let threads = thread_mails(&mails);
+        identify(&mut mails, &threads, &state);
+        let keys: Vec<Option<&str>> = mails.iter().map(|m| m.thread_id.as_ref().map(|k| k.as_str())).collect();
+        assert_eq!(keys, vec![Some("a@x"), Some("a@x"), Some("known"), None]);
+    }
+
+    #[test]
+    fn thread_update_and_load() {
+        let mut state = State::default();
+        let mut mails = vec![mail(1, "Message-ID: <a@x>"), mail(2, "Subject: no id")];
+        let threads = thread_mails(&mails);
+        identify(&mut mails, &threads, &state);
+        assert!(update(&mut mails[0], &mut state, |t| t.folders.push("Project-X".to_string())));
+        assert!(!update(&mut mails[1], &mut state, |t| t.muted = true));
+        // Reply comes in a later run
+        let mut mails = vec![mail(3, "Message-ID: <b@x>\r\nIn-Reply-To: <a@x>")];
+        let threads = thread_mails(&mails);
+        identify(&mut mails, &threads, &state);
+        assert_eq!(mails[0].thread_id, Some("a@x".to_string()));
+        load(&mut mails[0], &mut state);
+        assert_eq!(mails[0].thread.folders, vec!["Project-X"]);
+        assert_eq!(state.thread("a@x").unwrap().ids, vec!["a@x", "b@x"]);
+    }
+}
@@ -0,0 +1,90 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use error::*;
+
+/// Timezone used to read dates and times given in rules
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Timezone {
+    /// Timezone of the machine running narricky
+    #[default]
+    Local,
+    /// Timezone from tz database, like `Europe/Paris`
+    Named(Tz),
+}
+
+impl Timezone {
+    /// Parse timezone name, `local` or a name from tz database
+    pub fn parse(name: &str) -> Result<Timezone> {
+        if name.eq_ignore_ascii_case("local") {
+            return Ok(Timezone::Local);
+        }
+        match name.parse::<Tz>() {
+            Ok(tz) => Ok(Timezone::Named(tz)),
+            Err(_) => bail!(ErrorKind::InvalidTimezone(name.to_string())),
+        }
+    }
+
+    /// Wall clock date and time of date in this timezone
+    pub fn naive_local(&self, date: &DateTime<FixedOffset>) -> NaiveDateTime {
+        match *self {
+            Timezone::Local => date.with_timezone(&Local).naive_local(),
+            Timezone::Named(tz) => date.with_timezone(&tz).naive_local(),
+        }
+    }
+
+    /// Date of wall clock date and time in this timezone
+    ///
+    /// When clocks go back, the earliest date is returned. When clocks go forward and this time
+    /// doesn't exist, `None` is returned.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn from_local(&self, naive: &NaiveDateTime) -> Option<DateTime<Utc>> {
+        match *self {
+            Timezone::Local => {
+                Local
+                    .from_local_datetime(naive)
+                    .earliest()
+                    .map(|d| d.with_timezone(&Utc))
+            }
+            Timezone::Named(tz) => {
+                tz.from_local_datetime(naive).earliest().map(
+                    |d| d.with_timezone(&Utc),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn timezone_parse() {
+        assert_eq!(Timezone::parse("Local").unwrap(), Timezone::Local);
+        assert_eq!(
+            Timezone::parse("Europe/Paris").unwrap(),
+            Timezone::Named("Europe/Paris".parse::<Tz>().unwrap())
+        );
+        assert!(Timezone::parse("Europe/Nowhere").is_err());
+    }
+
+    #[test]
+    fn timezone_conversions() {
+        let paris = Timezone::parse("Europe/Paris").unwrap();
+        let date = DateTime::parse_from_rfc2822("Sat, 10 Jan 2026 12:00:00 +0000").unwrap();
+        assert_eq!(
+            paris.naive_local(&date),
+            NaiveDate::from_ymd(2026, 1, 10).and_hms(13, 0, 0)
+        );
+        assert_eq!(
+            paris.from_local(&NaiveDate::from_ymd(2026, 7, 1).and_hms(0, 0, 0)),
+            Some(Utc.ymd(2026, 6, 30).and_hms(22, 0, 0))
+        );
+        // Clocks go forward at 2:00 on last sunday of march
+        assert_eq!(
+            paris.from_local(&NaiveDate::from_ymd(2026, 3, 29).and_hms(2, 30, 0)),
+            None
+        );
+    }
+}